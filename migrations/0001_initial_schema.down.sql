@@ -0,0 +1,10 @@
+-- - drop everything created by the initial schema, children before parents
+DROP TABLE IF EXISTS Login;
+DROP TABLE IF EXISTS Notifications;
+DROP TABLE IF EXISTS Patients_Previous_Appointments;
+DROP TABLE IF EXISTS Appointments;
+DROP TABLE IF EXISTS Patients;
+DROP TABLE IF EXISTS Appointment_Prices;
+DROP TABLE IF EXISTS Appointment_Types;
+DROP TABLE IF EXISTS Doctors;
+DROP TABLE IF EXISTS Specialities;
@@ -0,0 +1,13 @@
+-- - long lived refresh tokens; only a hash of the token is stored, and a token
+-- - is revoked as soon as it is exchanged for a new pair (rotation)
+CREATE TABLE IF NOT EXISTS Refresh_Tokens (
+    id BIGSERIAL PRIMARY KEY,
+    token_hash VARCHAR(255) NOT NULL UNIQUE,
+    user_id BIGINT NOT NULL,
+    isdoctor BOOLEAN NOT NULL,
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_idx ON Refresh_Tokens (user_id, isdoctor);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS Doctor_Schedules;
+ALTER TABLE Appointment_Types DROP CONSTRAINT IF EXISTS chk_duration;
+ALTER TABLE Appointment_Types DROP COLUMN IF EXISTS duration_minutes;
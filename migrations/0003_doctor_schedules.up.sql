@@ -0,0 +1,23 @@
+-- - how long an appointment of each type takes; this is also the length of a bookable slot
+ALTER TABLE Appointment_Types ADD COLUMN IF NOT EXISTS duration_minutes INT NOT NULL DEFAULT 30;
+ALTER TABLE Appointment_Types ADD CONSTRAINT chk_duration CHECK (duration_minutes > 0);
+
+-- - weekly working hours of a doctor; a weekday (1 = Monday .. 7 = Sunday) can have
+-- - several intervals, e.g. a morning and an afternoon shift
+CREATE TABLE IF NOT EXISTS Doctor_Schedules (
+    id BIGSERIAL PRIMARY KEY,
+    doctor_id INT NOT NULL,
+    weekday SMALLINT NOT NULL,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    FOREIGN KEY (doctor_id) REFERENCES Doctors(id),
+    CONSTRAINT chk_weekday CHECK (weekday BETWEEN 1 AND 7),
+    CONSTRAINT chk_hours CHECK (start_time < end_time)
+);
+
+CREATE INDEX IF NOT EXISTS doctor_schedules_doctor_idx ON Doctor_Schedules (doctor_id);
+
+-- - doctors that already exist keep being bookable with regular office hours
+INSERT INTO Doctor_Schedules (doctor_id, weekday, start_time, end_time)
+SELECT d.id, w.weekday, '09:00', '17:00'
+FROM Doctors d CROSS JOIN generate_series(1, 5) AS w(weekday);
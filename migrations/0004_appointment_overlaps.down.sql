@@ -0,0 +1,3 @@
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS no_overlapping_appointments;
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS chk_ends_at;
+ALTER TABLE Appointments DROP COLUMN IF EXISTS ends_at;
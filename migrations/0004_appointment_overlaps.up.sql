@@ -0,0 +1,41 @@
+-- - every appointment now records when it ends, copied from its type's duration at booking
+-- - time, so that later changes to a duration don't move existing bookings
+ALTER TABLE Appointments ADD COLUMN IF NOT EXISTS ends_at TIMESTAMP;
+UPDATE Appointments a SET ends_at = a.date_time + make_interval(mins => t.duration_minutes)
+FROM Appointment_Types t WHERE t.id = a.appointment_type;
+ALTER TABLE Appointments ALTER COLUMN ends_at SET NOT NULL;
+ALTER TABLE Appointments ADD CONSTRAINT chk_ends_at CHECK (ends_at > date_time);
+
+-- - a doctor can never have two overlapping appointments that aren't cancelled; enforced by
+-- - the database so that two concurrent bookings can't both get through
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+-- - bookings made before this check could overlap; of every overlapping group the earliest
+-- - booking is kept and the others are cancelled, or the constraint below couldn't be added
+DO $$
+DECLARE
+    r RECORD;
+    cancelled INT := 0;
+BEGIN
+    FOR r IN
+        SELECT id, doctor_id, date_time, ends_at FROM ONLY Appointments
+        WHERE status <> 'cancelled' ORDER BY doctor_id, date_time, id
+    LOOP
+        IF EXISTS (
+            SELECT 1 FROM ONLY Appointments k
+            WHERE k.doctor_id = r.doctor_id AND k.status <> 'cancelled'
+            AND (k.date_time, k.id) < (r.date_time, r.id)
+            AND k.date_time < r.ends_at AND r.date_time < k.ends_at
+        ) THEN
+            UPDATE ONLY Appointments SET status = 'cancelled' WHERE id = r.id;
+            cancelled := cancelled + 1;
+        END IF;
+    END LOOP;
+    IF cancelled > 0 THEN
+        RAISE NOTICE 'Cancelled % appointments overlapping an earlier booking of the same doctor', cancelled;
+    END IF;
+END $$;
+
+ALTER TABLE Appointments ADD CONSTRAINT no_overlapping_appointments
+    EXCLUDE USING gist (doctor_id WITH =, tsrange(date_time, ends_at) WITH &&)
+    WHERE (status <> 'cancelled');
@@ -0,0 +1,5 @@
+DELETE FROM Notifications WHERE patient_id IS NULL;
+ALTER TABLE Notifications DROP CONSTRAINT IF EXISTS chk_recipient;
+ALTER TABLE Notifications DROP COLUMN IF EXISTS doctor_id;
+ALTER TABLE Notifications ALTER COLUMN patient_id SET NOT NULL;
+DROP TABLE IF EXISTS Appointment_Reschedules;
@@ -0,0 +1,17 @@
+-- - history of appointments being moved to another time; no foreign key to Appointments
+-- - since old appointments get moved to Patients_Previous_Appointments
+CREATE TABLE IF NOT EXISTS Appointment_Reschedules (
+    id BIGSERIAL PRIMARY KEY,
+    appointment_id BIGINT NOT NULL,
+    old_date_time TIMESTAMP NOT NULL,
+    new_date_time TIMESTAMP NOT NULL,
+    by_doctor BOOLEAN NOT NULL,
+    rescheduled_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS appointment_reschedules_appointment_idx ON Appointment_Reschedules (appointment_id);
+
+-- - notifications can now be addressed to doctors as well as patients
+ALTER TABLE Notifications ALTER COLUMN patient_id DROP NOT NULL;
+ALTER TABLE Notifications ADD COLUMN IF NOT EXISTS doctor_id INT REFERENCES Doctors(id);
+ALTER TABLE Notifications ADD CONSTRAINT chk_recipient CHECK ((patient_id IS NULL) <> (doctor_id IS NULL));
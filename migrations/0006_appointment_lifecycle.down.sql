@@ -0,0 +1,5 @@
+UPDATE Appointments SET status = 'scheduled' WHERE status = 'in_progress';
+UPDATE Appointments SET status = 'cancelled' WHERE status = 'no_show';
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS chk_status;
+ALTER TABLE Appointments ADD CONSTRAINT chk_status
+    CHECK (status IN ('scheduled', 'fulfilled', 'cancelled'));
@@ -0,0 +1,4 @@
+-- - appointments can now also be in progress or end with the patient not showing up
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS chk_status;
+ALTER TABLE Appointments ADD CONSTRAINT chk_status
+    CHECK (status IN ('scheduled', 'in_progress', 'fulfilled', 'no_show', 'cancelled'));
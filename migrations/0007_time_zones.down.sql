@@ -0,0 +1,17 @@
+ALTER TABLE Appointment_Reschedules
+    ALTER COLUMN old_date_time TYPE TIMESTAMP USING old_date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN new_date_time TYPE TIMESTAMP USING new_date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN rescheduled_at TYPE TIMESTAMP USING rescheduled_at AT TIME ZONE 'UTC';
+ALTER TABLE Notifications
+    ALTER COLUMN date_time TYPE TIMESTAMP USING date_time AT TIME ZONE 'UTC';
+
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS no_overlapping_appointments;
+ALTER TABLE Appointments
+    ALTER COLUMN date_time TYPE TIMESTAMP USING date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN ends_at TYPE TIMESTAMP USING ends_at AT TIME ZONE 'UTC';
+ALTER TABLE Appointments ADD CONSTRAINT no_overlapping_appointments
+    EXCLUDE USING gist (doctor_id WITH =, tsrange(date_time, ends_at) WITH &&)
+    WHERE (status <> 'cancelled');
+
+ALTER TABLE Patients DROP COLUMN IF EXISTS time_zone;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS time_zone;
@@ -0,0 +1,21 @@
+-- - every doctor and patient has an IANA time zone; doctors' working hours are in their zone
+-- - and times are shown to everyone in their own zone
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS time_zone VARCHAR(64) NOT NULL DEFAULT 'UTC';
+ALTER TABLE Patients ADD COLUMN IF NOT EXISTS time_zone VARCHAR(64) NOT NULL DEFAULT 'UTC';
+
+-- - times become instants; existing ones were wall clock times of the doctor, and every doctor
+-- - starts out in UTC. Patients_Previous_Appointments inherits the new column types
+ALTER TABLE Appointments DROP CONSTRAINT IF EXISTS no_overlapping_appointments;
+ALTER TABLE Appointments
+    ALTER COLUMN date_time TYPE TIMESTAMPTZ USING date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN ends_at TYPE TIMESTAMPTZ USING ends_at AT TIME ZONE 'UTC';
+ALTER TABLE Appointments ADD CONSTRAINT no_overlapping_appointments
+    EXCLUDE USING gist (doctor_id WITH =, tstzrange(date_time, ends_at) WITH &&)
+    WHERE (status <> 'cancelled');
+
+ALTER TABLE Notifications
+    ALTER COLUMN date_time TYPE TIMESTAMPTZ USING date_time AT TIME ZONE 'UTC';
+ALTER TABLE Appointment_Reschedules
+    ALTER COLUMN old_date_time TYPE TIMESTAMPTZ USING old_date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN new_date_time TYPE TIMESTAMPTZ USING new_date_time AT TIME ZONE 'UTC',
+    ALTER COLUMN rescheduled_at TYPE TIMESTAMPTZ USING rescheduled_at AT TIME ZONE 'UTC';
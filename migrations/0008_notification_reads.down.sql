@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS notifications_doctor_idx;
+DROP INDEX IF EXISTS notifications_patient_idx;
+ALTER TABLE Notifications DROP COLUMN IF EXISTS read_at;
@@ -0,0 +1,5 @@
+-- - when the recipient read the notification, NULL while unread
+ALTER TABLE Notifications ADD COLUMN IF NOT EXISTS read_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS notifications_patient_idx ON Notifications (patient_id, date_time);
+CREATE INDEX IF NOT EXISTS notifications_doctor_idx ON Notifications (doctor_id, date_time);
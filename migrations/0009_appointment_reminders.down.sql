@@ -0,0 +1 @@
+DROP TABLE IF EXISTS Appointment_Reminders;
@@ -0,0 +1,9 @@
+-- - reminders already sent, one per appointment and offset before its start, so that a
+-- - restarted server or a second instance never sends the same reminder twice. No foreign key
+-- - to Appointments since old appointments get moved to Patients_Previous_Appointments
+CREATE TABLE IF NOT EXISTS Appointment_Reminders (
+    appointment_id BIGINT NOT NULL,
+    offset_minutes INT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (appointment_id, offset_minutes)
+);
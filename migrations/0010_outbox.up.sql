@@ -0,0 +1,20 @@
+-- - emails and text messages waiting to be delivered; written in the same transaction as
+-- - whatever caused them, so nothing is lost if the server crashes before sending
+CREATE TABLE IF NOT EXISTS Outbox (
+    id BIGSERIAL PRIMARY KEY,
+    channel VARCHAR(16) NOT NULL,
+    -- - email address or phone number, depending on the channel
+    recipient VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    body TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    sent_at TIMESTAMPTZ,
+    CONSTRAINT chk_channel CHECK (channel IN ('email', 'sms')),
+    CONSTRAINT chk_outbox_status CHECK (status IN ('pending', 'sent', 'failed'))
+);
+
+CREATE INDEX IF NOT EXISTS outbox_due_idx ON Outbox (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1,30 @@
+-- - URLs that get a signed POST for every matching appointment event. A doctor's webhooks get the
+-- - events of their own appointments, webhooks without a doctor were set up by an admin and get all
+CREATE TABLE IF NOT EXISTS Webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    doctor_id INT REFERENCES Doctors(id),
+    url TEXT NOT NULL,
+    -- - secret the payloads are signed with (HMAC-SHA256)
+    secret VARCHAR(255) NOT NULL,
+    events TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- - every event sent (or to be sent) to a webhook, doubles as the delivery log
+CREATE TABLE IF NOT EXISTS Webhook_Deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES Webhooks(id) ON DELETE CASCADE,
+    event VARCHAR(64) NOT NULL,
+    payload TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_status_code INT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    CONSTRAINT chk_delivery_status CHECK (status IN ('pending', 'delivered', 'failed'))
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx ON Webhook_Deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_idx ON Webhook_Deliveries (webhook_id, created_at);
@@ -0,0 +1,7 @@
+DROP TRIGGER IF EXISTS specialities_search ON Specialities;
+DROP FUNCTION IF EXISTS specialities_search_update();
+DROP TRIGGER IF EXISTS doctors_search ON Doctors;
+DROP FUNCTION IF EXISTS doctors_search_update();
+DROP INDEX IF EXISTS doctors_search_vector_idx;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS search_text;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS search_vector;
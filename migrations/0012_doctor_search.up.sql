@@ -0,0 +1,44 @@
+-- - trigram similarity, used by /search when a query has typos
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- - what /search looks at: the doctor's name, city and address and the name and description of their
+-- - speciality. search_vector is for full-text matching (names weigh most, then what the
+-- - speciality is about, then where the doctor is),
+-- - search_text is the same text as is, for trigram matching
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS search_vector TSVECTOR;
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS search_text TEXT;
+
+CREATE OR REPLACE FUNCTION doctors_search_update() RETURNS TRIGGER AS $$
+BEGIN
+    SELECT setweight(to_tsvector('english', coalesce(NEW.name, '')), 'A') ||
+        setweight(to_tsvector('english', coalesce(s.name, '')), 'A') ||
+        setweight(to_tsvector('english', coalesce(s.description, '')), 'B') ||
+        setweight(to_tsvector('english', coalesce(NEW.city, '')), 'C') ||
+        setweight(to_tsvector('english', coalesce(NEW.address, '')), 'D'),
+        concat_ws(' ', NEW.name, s.name, NEW.city, NEW.address, s.description)
+    INTO NEW.search_vector, NEW.search_text
+    FROM Specialities s WHERE s.id = NEW.speciality_id;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS doctors_search ON Doctors;
+CREATE TRIGGER doctors_search BEFORE INSERT OR UPDATE OF name, city, address, speciality_id ON Doctors
+    FOR EACH ROW EXECUTE FUNCTION doctors_search_update();
+
+-- - renaming or redescribing a speciality refreshes its doctors
+CREATE OR REPLACE FUNCTION specialities_search_update() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE Doctors SET speciality_id = speciality_id WHERE speciality_id = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS specialities_search ON Specialities;
+CREATE TRIGGER specialities_search AFTER UPDATE OF name, description ON Specialities
+    FOR EACH ROW EXECUTE FUNCTION specialities_search_update();
+
+-- - fills in the doctors that are already there
+UPDATE Doctors SET speciality_id = speciality_id;
+
+CREATE INDEX IF NOT EXISTS doctors_search_vector_idx ON Doctors USING gin (search_vector);
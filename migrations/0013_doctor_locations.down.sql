@@ -0,0 +1,4 @@
+DROP FUNCTION IF EXISTS distance_km(DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION);
+ALTER TABLE Doctors DROP CONSTRAINT IF EXISTS chk_coordinates;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS longitude;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS latitude;
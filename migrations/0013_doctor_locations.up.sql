@@ -0,0 +1,17 @@
+-- - where a doctor's practice is, for nearest-doctor searches; doctors without coordinates
+-- - are left out of searches with a radius
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION;
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION;
+ALTER TABLE Doctors ADD CONSTRAINT chk_coordinates CHECK (
+    (latitude IS NULL AND longitude IS NULL) OR
+    (latitude BETWEEN -90 AND 90 AND longitude BETWEEN -180 AND 180)
+);
+
+-- - great-circle distance between two points in kilometres (haversine formula)
+CREATE OR REPLACE FUNCTION distance_km(lat1 DOUBLE PRECISION, lon1 DOUBLE PRECISION,
+    lat2 DOUBLE PRECISION, lon2 DOUBLE PRECISION) RETURNS DOUBLE PRECISION AS $$
+    SELECT 2 * 6371.0 * asin(least(1.0, sqrt(
+        sin(radians(lat2 - lat1) / 2) ^ 2 +
+        cos(radians(lat1)) * cos(radians(lat2)) * sin(radians(lon2 - lon1) / 2) ^ 2
+    )))
+$$ LANGUAGE sql IMMUTABLE STRICT;
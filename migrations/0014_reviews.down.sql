@@ -0,0 +1,2 @@
+DROP VIEW IF EXISTS Doctor_Ratings;
+DROP TABLE IF EXISTS Reviews;
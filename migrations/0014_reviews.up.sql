@@ -0,0 +1,26 @@
+-- - ratings (1 to 5) patients give doctors after a fulfilled appointment, one per appointment.
+-- - appointment_id has no foreign key since archived appointments move to
+-- - Patients_Previous_Appointments, which a foreign key to Appointments doesn't see
+CREATE TABLE IF NOT EXISTS Reviews (
+    id BIGSERIAL PRIMARY KEY,
+    appointment_id BIGINT NOT NULL UNIQUE,
+    doctor_id INT NOT NULL REFERENCES Doctors(id),
+    patient_id INT NOT NULL REFERENCES Patients(id),
+    rating SMALLINT NOT NULL,
+    comment TEXT,
+    -- - the doctor's public answer
+    reply TEXT,
+    replied_at TIMESTAMPTZ,
+    -- - hidden reviews were taken down by an admin; they don't count towards the rating
+    hidden BOOLEAN NOT NULL DEFAULT false,
+    hidden_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT chk_rating CHECK (rating BETWEEN 1 AND 5)
+);
+
+CREATE INDEX IF NOT EXISTS reviews_doctor_idx ON Reviews (doctor_id, created_at) WHERE NOT hidden;
+
+-- - average rating and number of visible reviews of every reviewed doctor
+CREATE OR REPLACE VIEW Doctor_Ratings AS
+    SELECT doctor_id, round(avg(rating), 2)::DOUBLE PRECISION AS rating, count(*) AS review_count
+    FROM Reviews WHERE NOT hidden GROUP BY doctor_id;
@@ -0,0 +1,6 @@
+ALTER TABLE Doctors DROP CONSTRAINT IF EXISTS chk_years_of_experience;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS photo_url;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS languages;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS years_of_experience;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS qualifications;
+ALTER TABLE Doctors DROP COLUMN IF EXISTS bio;
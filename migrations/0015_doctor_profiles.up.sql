@@ -0,0 +1,7 @@
+-- - what doctors tell patients about themselves on their profile
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS bio TEXT;
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS qualifications TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS years_of_experience INT;
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS languages TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE Doctors ADD COLUMN IF NOT EXISTS photo_url TEXT;
+ALTER TABLE Doctors ADD CONSTRAINT chk_years_of_experience CHECK (years_of_experience BETWEEN 0 AND 80);
@@ -0,0 +1,2 @@
+ALTER TABLE Appointment_Prices DROP CONSTRAINT IF EXISTS chk_price;
+DROP TABLE IF EXISTS Appointment_Price_History;
@@ -0,0 +1,14 @@
+-- - every change doctors make to what they offer: old_price is null when a type is first offered,
+-- - new_price is null when it is withdrawn
+CREATE TABLE IF NOT EXISTS Appointment_Price_History (
+    id BIGSERIAL PRIMARY KEY,
+    doctor_id INT NOT NULL REFERENCES Doctors(id),
+    appointment_type INT NOT NULL REFERENCES Appointment_Types(id),
+    old_price INT,
+    new_price INT,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS appointment_price_history_doctor_idx ON Appointment_Price_History (doctor_id, changed_at);
+
+ALTER TABLE Appointment_Prices ADD CONSTRAINT chk_price CHECK (price >= 0);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS appointment_prices_price_idx;
+ALTER TABLE Appointment_Prices DROP CONSTRAINT IF EXISTS chk_modes;
+ALTER TABLE Appointment_Prices DROP COLUMN IF EXISTS modes;
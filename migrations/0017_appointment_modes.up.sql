@@ -0,0 +1,7 @@
+-- - whether the doctor sees an appointment type in person, virtually or both; everything offered
+-- - so far could be booked either way
+ALTER TABLE Appointment_Prices ADD COLUMN IF NOT EXISTS modes TEXT[] NOT NULL DEFAULT '{physical,virtual}';
+ALTER TABLE Appointment_Prices ADD CONSTRAINT chk_modes
+    CHECK (cardinality(modes) > 0 AND modes <@ ARRAY['physical', 'virtual']);
+
+CREATE INDEX IF NOT EXISTS appointment_prices_price_idx ON Appointment_Prices (appointment_type, price);
@@ -0,0 +1,28 @@
+//background task that periodically moves old appointments out of the active table
+use chrono::{Duration as ChronoDuration, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::database::{env_or, Database};
+
+pub fn spawn(conn: Arc<Database>) {
+    let after_days: i64 = env_or("ARCHIVE_AFTER_DAYS", 30);
+    let every_secs: u64 = env_or("ARCHIVE_INTERVAL_SECS", 3600);
+    tracing::debug!(
+        "Archiving appointments older than {} days every {}s",
+        after_days,
+        every_secs
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(every_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let cutoff = Utc::now() - ChronoDuration::days(after_days);
+            match conn.archive_appointments(cutoff).await {
+                Ok(0) => tracing::debug!("No appointments to archive"),
+                Ok(n) => tracing::info!("Archived {} appointments", n),
+                Err(_) => tracing::error!("Archiving appointments failed, retrying next time"),
+            }
+        }
+    });
+}
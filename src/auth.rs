@@ -0,0 +1,168 @@
+//extractors that turn the Authorization header into the identity of the caller
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{
+        header::{HeaderName, AUTHORIZATION},
+        request::Parts,
+    },
+};
+use std::sync::Arc;
+
+use crate::database::Database;
+use crate::db_structs::Jwt;
+use crate::error::AppError;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Patient,
+    Doctor,
+}
+
+//any logged in user; rejects with 401 if the token is missing or invalid
+pub struct AuthUser {
+    pub id: i64,
+    pub role: Role,
+}
+
+//logged in patient; rejects doctors with 403
+pub struct RequirePatient(pub AuthUser);
+
+//logged in doctor; rejects patients with 403
+pub struct RequireDoctor(pub AuthUser);
+
+//caller with the ADMIN_API_KEY in the X-Admin-Key header
+pub struct RequireAdmin;
+
+//an admin or a logged in doctor, holding the doctor's ID or None for an admin
+pub struct DoctorOrAdmin(pub Option<i64>);
+
+impl AuthUser {
+    //for routes naming a patient or doctor in the path, which only they can access
+    pub fn ensure_is(&self, role: Role, id: i64) -> Result<(), AppError> {
+        if self.role != role || self.id != id {
+            return Err(AppError::Forbidden(String::from(
+                "You can only access your own records",
+            )));
+        }
+        Ok(())
+    }
+}
+
+static ADMIN_KEY: HeaderName = HeaderName::from_static("x-admin-key");
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(entry) = parts.headers.get(AUTHORIZATION) else {
+            tracing::error!("No JWT given in request, denying access..");
+            return Err(AppError::Unauthorized(String::from("No token given")));
+        };
+        let Ok(rawjwt) = entry.to_str() else {
+            tracing::error!("JWT can't be parsed, denying access..");
+            return Err(AppError::Unauthorized(String::from(
+                "Token can't be parsed",
+            )));
+        };
+        let conn = Arc::<Database>::from_ref(state);
+        let jwt = conn.verify_jwt(rawjwt)?;
+        tracing::debug!("Verified and parsed JWT");
+        Ok(AuthUser::from(jwt))
+    }
+}
+
+impl From<Jwt> for AuthUser {
+    fn from(jwt: Jwt) -> Self {
+        AuthUser {
+            id: jwt.id,
+            role: if jwt.isdoctor {
+                Role::Doctor
+            } else {
+                Role::Patient
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequirePatient
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if user.role != Role::Patient {
+            return Err(AppError::Forbidden(String::from(
+                "Only patients can access this resource",
+            )));
+        }
+        Ok(RequirePatient(user))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequireDoctor
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if user.role != Role::Doctor {
+            return Err(AppError::Forbidden(String::from(
+                "Only doctors can access this resource",
+            )));
+        }
+        Ok(RequireDoctor(user))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequireAdmin
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(key) = parts.headers.get(&ADMIN_KEY) else {
+            return Err(AppError::Unauthorized(String::from("No admin key given")));
+        };
+        let conn = Arc::<Database>::from_ref(state);
+        if !key.to_str().is_ok_and(|key| conn.is_admin_key(key)) {
+            tracing::error!("Wrong admin key given, denying access..");
+            return Err(AppError::Forbidden(String::from("Invalid admin key")));
+        }
+        Ok(RequireAdmin)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DoctorOrAdmin
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(&ADMIN_KEY) {
+            RequireAdmin::from_request_parts(parts, state).await?;
+            return Ok(DoctorOrAdmin(None));
+        }
+        let RequireDoctor(user) = RequireDoctor::from_request_parts(parts, state).await?;
+        Ok(DoctorOrAdmin(Some(user.id)))
+    }
+}
@@ -1,19 +1,62 @@
 //create structs for interfacing with the database
-use argon_hash_password;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use dotenvy::dotenv;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use sqlx::{postgres::PgPoolOptions, postgres::PgRow, Pool, Postgres, Row};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    postgres::{PgArguments, PgConnection, PgPoolOptions, PgRow},
+    query::QueryAs,
+    Pool, Postgres, QueryBuilder, Row,
+};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::env;
-use tracing;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast;
 
 use crate::db_structs::*;
+use crate::delivery::{self, Channel};
+use crate::error::AppError;
+use crate::events::{self, AppointmentEvent};
+use crate::geocoding::{Coordinates, Geocoder, NoGeocoder, Origin};
+use crate::pagination::{Cursor, Keyset};
+use crate::slots::{self, TimeRange};
+use crate::status::AppointmentStatus;
+use crate::webhooks;
+use crate::zones;
 
 pub struct Database {
     jwt_secret: Vec<u8>,
+    jwt_issuer: String,
+    //lifetimes in seconds
+    access_token_ttl: i64,
+    refresh_token_ttl: i64,
+    //channels emails and text messages are queued for
+    channels: Vec<Channel>,
+    //appointment events of all server instances, see events.rs
+    events: broadcast::Sender<AppointmentEvent>,
+    //hash of ADMIN_API_KEY; admin endpoints are disabled if it isn't set
+    admin_key_hash: Option<String>,
+    //finds the coordinates of doctors that don't give them, none until set_geocoder is called
+    geocoder: Box<dyn Geocoder>,
     connection: Pool<Postgres>,
 }
 
+//reads an optional setting from the environment, falling back to the default if unset or unparsable
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(val) => val.parse().unwrap_or_else(|_| {
+            tracing::error!("Could not parse {}, using default", key);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+//called once at startup; the resulting pool is shared by all handlers
 pub async fn init() -> Option<Database> {
     dotenv().ok();
     let Ok(url) = env::var("DATABASE_URL") else {
@@ -24,320 +67,2099 @@ pub async fn init() -> Option<Database> {
         tracing::error!("Couldn't find SECRET, aborting");
         return None;
     };
-    tracing::debug!("Found database URL: {} and secret", url);
-    match PgPoolOptions::new().connect(&url).await {
+    let max_connections = env_or("DB_MAX_CONNECTIONS", 10);
+    let acquire_timeout = env_or("DB_ACQUIRE_TIMEOUT_SECS", 5);
+    let idle_timeout = env_or("DB_IDLE_TIMEOUT_SECS", 600);
+    tracing::debug!(
+        "Found database URL and secret, pool of {} connections (acquire timeout {}s, idle timeout {}s)",
+        max_connections,
+        acquire_timeout,
+        idle_timeout
+    );
+    match PgPoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(Duration::from_secs(acquire_timeout))
+        .idle_timeout(Duration::from_secs(idle_timeout))
+        .connect(&url)
+        .await
+    {
         Ok(pool) => {
             tracing::debug!("Connected to database!");
-            return Some(Database {
+            Some(Database {
                 connection: pool,
                 jwt_secret: sec.as_bytes().to_vec(),
-            });
+                jwt_issuer: env_or("JWT_ISSUER", String::from("excalibur23")),
+                access_token_ttl: env_or("ACCESS_TOKEN_TTL_SECS", 15 * 60),
+                refresh_token_ttl: env_or("REFRESH_TOKEN_TTL_SECS", 30 * 24 * 60 * 60),
+                channels: delivery::enabled_channels(),
+                events: broadcast::channel(EVENT_BUFFER).0,
+                admin_key_hash: env::var("ADMIN_API_KEY")
+                    .ok()
+                    .filter(|key| !key.is_empty())
+                    .map(|key| hash_token(&key)),
+                geocoder: Box::new(NoGeocoder),
+            })
         }
         Err(e) => {
-            tracing::error!("Could not connect using URL {}", url);
+            tracing::error!("Could not connect to database");
             tracing::error!("Error: {}", e);
-            return None;
+            None
         }
     }
 }
 
 impl Database {
-    async fn get_query_result<ResultStruct, DB>(&self, query: &String) -> Vec<ResultStruct>
+    pub fn pool(&self) -> &Pool<Postgres> {
+        &self.connection
+    }
+
+    pub fn events(&self) -> &broadcast::Sender<AppointmentEvent> {
+        &self.events
+    }
+
+    //only the server and `excalibur23 geocode` need one, so migrations work whatever GEOCODER is
+    pub fn set_geocoder(&mut self, geocoder: Box<dyn Geocoder>) {
+        self.geocoder = geocoder;
+    }
+
+    //compares hashes so the time taken doesn't give away how much of the key was right
+    pub fn is_admin_key(&self, key: &str) -> bool {
+        self.admin_key_hash
+            .as_deref()
+            .is_some_and(|hash| hash == hash_token(key))
+    }
+
+    //runs a query with its bound arguments and collects every row
+    async fn get_query_result<ResultStruct>(
+        &self,
+        query: QueryAs<'_, Postgres, ResultStruct, PgArguments>,
+    ) -> Result<Vec<ResultStruct>, AppError>
     where
-        ResultStruct: for<'r> sqlx::FromRow<'r, <DB as sqlx::Database>::Row>,
+        ResultStruct: for<'r> sqlx::FromRow<'r, PgRow>,
         ResultStruct: Unpin,
         ResultStruct: Send,
-        DB: sqlx::Database<Row = PgRow>,
     {
-        match sqlx::query_as::<_, ResultStruct>(&query)
-            .fetch_all(&self.connection)
-            .await
-        {
-            Ok(result) => result,
-            Err(e) => {
-                tracing::error!("Error while running query: {}", e);
-                let empty: Vec<ResultStruct> = Vec::new();
-                empty
-            }
-        }
+        Ok(query.fetch_all(&self.connection).await?)
     }
 
-    pub async fn view_prescriptions(&self, patient_id: i64) -> Vec<Prescriptions> {
-        let query = format!("
-                    (select d.name as docname, TO_CHAR(a.date_time, 'YYYY-MM-DD HH24:MM:SS') as timestamp, a.prescription as prescription
-                    from patients_previous_appointments a
-                    join doctors d on d.id = a.doctor_id
-                    where a.patient_id = {}
-                    order by timestamp desc)
-                    UNION
-                    (select d.name as docname, TO_CHAR(a.date_time, 'YYYY-MM-DD HH24:MM:SS') as timestamp, a.prescription as prescription
+    //runs a query for the rows after the cursor, limited to cursor.fetch_limit(), as a page
+    async fn get_page<ResultStruct>(
+        &self,
+        query: QueryAs<'_, Postgres, ResultStruct, PgArguments>,
+        cursor: &Cursor<ResultStruct>,
+    ) -> Result<Page<ResultStruct>, AppError>
+    where
+        ResultStruct: for<'r> sqlx::FromRow<'r, PgRow>,
+        ResultStruct: Keyset,
+        ResultStruct: Unpin,
+        ResultStruct: Send,
+    {
+        let rows = self.get_query_result(query).await?;
+        Ok(cursor.page(rows))
+    }
+
+    //newest first
+    pub async fn view_prescriptions(
+        &self,
+        patient_id: i64,
+        page: &CursorQuery,
+    ) -> Result<Page<Prescriptions>, AppError> {
+        let cursor = Cursor::<Prescriptions>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        let tz = self.patient_zone(patient_id).await?;
+        //appointments is the parent of patients_previous_appointments, so this reads archived rows too
+        let query = sqlx::query_as("
+                    select a.id, d.name as docname, a.date_time as timestamp, a.prescription as prescription
                     from appointments a
                     join doctors d on d.id = a.doctor_id
-                    where a.patient_id = {}
-                    order by timestamp desc)
-                    ;", patient_id, patient_id);
-        self.get_query_result::<Prescriptions, Postgres>(&query)
-            .await
+                    where a.patient_id = $1 and a.prescription is not null
+                    and ($2::timestamptz is null or (a.date_time, a.id) < ($2, $3))
+                    order by a.date_time desc, a.id desc limit $4
+                    ;")
+            .bind(patient_id)
+            .bind(before)
+            .bind(before_id)
+            .bind(cursor.fetch_limit());
+        let mut prescriptions = self.get_page(query, &cursor).await?;
+        for p in &mut prescriptions.items {
+            p.timestamp = zones::localize(&p.timestamp, tz);
+        }
+        Ok(prescriptions)
     }
 
-    pub async fn view_prev_appointments(&self, patient_id: i64) -> Vec<PrevAppointments> {
-        let query = format!("
-                    (select d.name as docname, TO_CHAR(a.date_time, 'YYYY-MM-DD HH24:MM:SS') as timestamp, a.type as apptype, a.status as appstatus, a.prescription as prescription, p.name as appname
-                    from patients_previous_appointments a
-                    join doctors d on d.id = a.doctor_id
-                    join specialities p on p.id = a.appointment_type
-                    where a.patient_id = {}
-                    order by timestamp desc)
-                    UNION
-                    (select d.name as docname, TO_CHAR(a.date_time, 'YYYY-MM-DD HH24:MM:SS') as timestamp, a.type as apptype, a.status as appstatus, a.prescription as prescription, p.name as appname
+    //newest first
+    pub async fn view_prev_appointments(
+        &self,
+        patient_id: i64,
+        page: &CursorQuery,
+    ) -> Result<Page<PrevAppointments>, AppError> {
+        let cursor = Cursor::<PrevAppointments>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        let tz = self.patient_zone(patient_id).await?;
+        //appointments is the parent of patients_previous_appointments, so this reads archived rows too
+        let query = sqlx::query_as("
+                    select a.id as id, d.name as docname, a.date_time as timestamp, a.type as apptype, a.status as appstatus, a.prescription as prescription, t.name as appname
                     from appointments a
                     join doctors d on d.id = a.doctor_id
-                    join specialities p on p.id = a.appointment_type
-                    where a.patient_id = {}
-                    order by timestamp desc)
-                    ;", patient_id, patient_id);
-        self.get_query_result::<PrevAppointments, Postgres>(&query)
-            .await
+                    join appointment_types t on t.id = a.appointment_type
+                    where a.patient_id = $1
+                    and ($2::timestamptz is null or (a.date_time, a.id) < ($2, $3))
+                    order by a.date_time desc, a.id desc limit $4
+                    ;")
+            .bind(patient_id)
+            .bind(before)
+            .bind(before_id)
+            .bind(cursor.fetch_limit());
+        let mut appointments = self.get_page(query, &cursor).await?;
+        for a in &mut appointments.items {
+            a.timestamp = zones::localize(&a.timestamp, tz);
+        }
+        Ok(appointments)
     }
 
-    pub async fn view_same_city_doctors(&self, city: String) -> Vec<DoctorInfo> {
-        let query = format!("
-                    select d.id as docid, d.name as docname, s.name as specname, d.address as address
-                    from doctors d
-                    join specialities s on s.id = d.speciality_id
-                    where d.city = '{}'
-                    ;", city);
-        self.get_query_result::<DoctorInfo, Postgres>(&query).await
+    //nearest first if searching from somewhere
+    pub async fn view_same_city_doctors(
+        &self,
+        city: &str,
+        origin: Option<Origin>,
+        page: &CursorQuery,
+    ) -> Result<Page<DoctorInfo>, AppError> {
+        let cursor = Cursor::<DoctorInfo>::parse(page)?;
+        let (after_distance, after_id) = match cursor.after {
+            Some((distance, id)) => (distance, Some(id)),
+            None => (None, None),
+        };
+        let (at, radius_km) = origin_params(origin);
+        let query = sqlx::query_as(
+            "
+                    select * from (
+                        select d.id as docid, d.name as docname, s.name as specname, d.address as address,
+                        d.latitude, d.longitude, distance_km(d.latitude, d.longitude, $2, $3) as distance_km,
+                        r.rating, coalesce(r.review_count, 0) as review_count
+                        from doctors d
+                        join specialities s on s.id = d.speciality_id
+                        left join doctor_ratings r on r.doctor_id = d.id
+                        where ($1 = '' or d.city = $1)
+                    ) found
+                    where ($4::float8 is null or distance_km <= $4)
+                    and ($6::bigint is null
+                        or (coalesce(distance_km, 'infinity'), docid) > (coalesce($5::float8, 'infinity'), $6))
+                    order by coalesce(distance_km, 'infinity'), docid limit $7
+                    ;",
+        )
+        .bind(city)
+        .bind(at.map(|c| c.latitude))
+        .bind(at.map(|c| c.longitude))
+        .bind(radius_km)
+        .bind(after_distance)
+        .bind(after_id)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
+    }
+
+    //full-text search over doctor name, speciality, city and address; doctors matching more of the
+    //words rank higher. If no word matches, falls back to doctors with similarly spelled words
+    pub async fn search_doctors(
+        &self,
+        search: &SearchQuery,
+        page: &CursorQuery,
+    ) -> Result<Page<DoctorSearchResult>, AppError> {
+        let cursor = Cursor::<DoctorSearchResult>::parse(page)?;
+        let (after_rank, after_id) = cursor.after.unzip();
+        //only letters and digits are kept, so the words can't be read as tsquery operators
+        let words: Vec<String> = search
+            .q
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .take(MAX_SEARCH_WORDS)
+            .map(str::to_lowercase)
+            .collect();
+        if words.is_empty() {
+            return Err(AppError::Validation(String::from(
+                "q must contain at least one word",
+            )));
+        }
+        let query = sqlx::query_as(
+            "with candidates as (
+                select d.id, d.name, s.name as specname, d.city, d.address, d.search_vector, d.search_text
+                from doctors d
+                join specialities s on s.id = d.speciality_id
+                where ($3::text is null or lower(d.city) = lower($3))
+                and ($4::bigint is null or d.speciality_id = $4)
+                and ($5::bigint is null or exists (
+                    select 1 from appointment_prices p where p.doctor_id = d.id and p.appointment_type = $5
+                ))
+            ), matches as (
+                select id, ts_rank_cd(search_vector, to_tsquery('english', $1)) as rank, false as fuzzy
+                from candidates where search_vector @@ to_tsquery('english', $1)
+            ), words as (
+                --stop words like the would be similar to nearly every doctor
+                select w from unnest($2::text[]) w where numnode(plainto_tsquery('english', w)) > 0
+            ), lookalikes as (
+                select id, (
+                    select sum(word_similarity(w, search_text)) from words where w <% search_text
+                ) as rank, true as fuzzy
+                from candidates
+                where not exists (select 1 from matches)
+                and exists (select 1 from words where w <% search_text)
+            )
+            select c.id as docid, c.name as docname, c.specname, c.city, c.address, m.rank, m.fuzzy
+            from (select * from matches union all select * from lookalikes) m
+            join candidates c on c.id = m.id
+            where $6::real is null or m.rank < $6 or (m.rank = $6 and c.id > $7)
+            order by m.rank desc, c.id
+            limit $8",
+        )
+        .bind(words.join(" | "))
+        .bind(&words)
+        .bind(&search.city)
+        .bind(search.speciality)
+        .bind(search.apptype)
+        .bind(after_rank)
+        .bind(after_id)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
     }
 
-    pub async fn view_patient_info(&self, patient_id: i64) -> Vec<PatientInfo> {
-        let query = format!(
+    pub async fn view_patient_info(&self, patient_id: i64) -> Result<PatientInfo, AppError> {
+        sqlx::query_as(
             "
                     select name, email, phone
                     from patients
-                    where id = {}
+                    where id = $1
                     ;",
-            patient_id
+        )
+        .bind(patient_id)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No patient with ID {}", patient_id)))
+    }
+
+    //doctors offering appointment types that match the filters, with their prices; filters left
+    //out or empty don't filter. Every filter is added as a bind parameter and the sort order
+    //comes from a fixed list
+    pub async fn view_doctor_prices(
+        &self,
+        filter: &FindQuery,
+        origin: Option<Origin>,
+        page: &CursorQuery,
+    ) -> Result<Page<FoundDoctor>, AppError> {
+        let sort = FindSort::parse(filter.sort.as_deref(), origin.is_some())?;
+        let cursor = Cursor::<FoundDoctor>::parse(page)?;
+        if filter.min_price.is_some_and(|p| p < 0) || filter.max_price.is_some_and(|p| p < 0) {
+            return Err(AppError::Validation(String::from(
+                "min_price and max_price can't be negative",
+            )));
+        }
+        if let (Some(min), Some(max)) = (filter.min_price, filter.max_price) {
+            if min > max {
+                return Err(AppError::Validation(String::from(
+                    "min_price can't be more than max_price",
+                )));
+            }
+        }
+        if filter.min_rating.is_some_and(|r| !(1.0..=5.0).contains(&r)) {
+            return Err(AppError::Validation(String::from(
+                "min_rating must be between 1 and 5",
+            )));
+        }
+        if let Some(mode) = filter.mode.as_deref() {
+            check_mode(mode)?;
+        }
+        let languages: Vec<String> = filter
+            .languages
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if languages.len() > MAX_PROFILE_LIST_LENGTH {
+            return Err(AppError::Validation(format!(
+                "At most {} languages can be asked for",
+                MAX_PROFILE_LIST_LENGTH
+            )));
+        }
+        let (at, radius_km) = origin_params(origin);
+        let mut query = QueryBuilder::<Postgres>::new("select *, ");
+        query.push(sort.value()).push(
+            "::float8 as sort_value from (
+                select d.id as docid, d.name as docname, d.city as city, d.address as address, t.name as apptype, t.id as appid, p.price,
+                p.modes, t.duration_minutes, d.time_zone, d.latitude, d.longitude,
+                distance_km(d.latitude, d.longitude, ",
         );
-        self.get_query_result::<PatientInfo, Postgres>(&query).await
+        query
+            .push_bind(at.map(|c| c.latitude))
+            .push(", ")
+            .push_bind(at.map(|c| c.longitude))
+            .push(
+                ") as distance_km, r.rating, coalesce(r.review_count, 0) as review_count
+                from doctors d
+                join appointment_types t on d.speciality_id = t.speciality_id
+                join appointment_prices p on d.id = p.doctor_id and t.id = p.appointment_type
+                left join doctor_ratings r on r.doctor_id = d.id
+                where true",
+            );
+        if !filter.apptype.is_empty() {
+            query.push(" and t.name = ").push_bind(&filter.apptype);
+        }
+        if !filter.city.is_empty() {
+            query.push(" and d.city = ").push_bind(&filter.city);
+        }
+        if let Some(speciality) = filter.speciality {
+            query.push(" and d.speciality_id = ").push_bind(speciality);
+        }
+        if let Some(min) = filter.min_price {
+            query.push(" and p.price >= ").push_bind(min);
+        }
+        if let Some(max) = filter.max_price {
+            query.push(" and p.price <= ").push_bind(max);
+        }
+        if let Some(mode) = &filter.mode {
+            query.push(" and ").push_bind(mode).push(" = any(p.modes)");
+        }
+        if !languages.is_empty() {
+            query
+                .push(" and array(select lower(l) from unnest(d.languages) l) @> ")
+                .push_bind(languages);
+        }
+        if let Some(min) = filter.min_rating {
+            query.push(" and r.rating >= ").push_bind(min);
+        }
+        query.push(") found where true");
+        if let Some(radius_km) = radius_km {
+            query.push(" and distance_km <= ").push_bind(radius_km);
+        }
+        let (key_start, key_end) = sort.key();
+        //soonest is sorted and paginated once the slots are known
+        if let (Some((value, docid, appid)), false) = (&cursor.after, sort == FindSort::Soonest) {
+            query
+                .push(" and (")
+                .push(key_start)
+                .push(sort.value())
+                .push(key_end)
+                .push(", docid, appid) > (")
+                .push(key_start)
+                .push_bind(*value)
+                .push(key_end)
+                .push(", ")
+                .push_bind(*docid)
+                .push(", ")
+                .push_bind(*appid)
+                .push(")");
+        }
+        query
+            .push(" order by ")
+            .push(key_start)
+            .push(sort.value())
+            .push(key_end)
+            .push(", docid, appid");
+        let with_slots = filter.available_on.is_some() || sort == FindSort::Soonest;
+        //without slots to check the page can be cut in the query, otherwise slots are worked out
+        //for at most MAX_SLOT_CANDIDATES doctors
+        query.push(" limit ").push_bind(if with_slots {
+            MAX_SLOT_CANDIDATES + 1
+        } else {
+            cursor.fetch_limit()
+        });
+        let mut found: Vec<DoctorPrices> = self.get_query_result(query.build_query_as()).await?;
+        if !with_slots {
+            let found = found
+                .into_iter()
+                .map(|doctor| FoundDoctor {
+                    doctor,
+                    next_available: None,
+                })
+                .collect();
+            return Ok(cursor.page(found));
+        }
+        let more = found.len() as i64 > MAX_SLOT_CANDIDATES;
+        if sort == FindSort::Soonest {
+            //the soonest slots can only be known by looking at every candidate
+            if more {
+                return Err(AppError::Validation(format!(
+                    "More than {} offers match, narrow the search down to sort by soonest",
+                    MAX_SLOT_CANDIDATES
+                )));
+            }
+            let mut found = self
+                .with_next_slots(found, filter.available_on, true)
+                .await?;
+            if let Some(after) = &cursor.after {
+                found.retain(|f| soonest_order(&f.key(), after) == Ordering::Greater);
+            }
+            return Ok(cursor.page(found));
+        }
+        //rows come in key order, so a page that ran out of candidates carries on after the last
+        //one looked at, even if fewer than limit doctors were free that day
+        found.truncate(MAX_SLOT_CANDIDATES as usize);
+        let scanned_to = found.last().filter(|_| more).map(|d| d.key());
+        let found = self
+            .with_next_slots(found, filter.available_on, false)
+            .await?;
+        Ok(cursor.filtered_page(found, scanned_to))
     }
 
-    pub async fn view_doctor_prices(&self, city: &String, apptype: &String) -> Vec<DoctorPrices> {
-        let iscityspecified = match city.is_empty() {
-            false => format!("and d.city = '{}'", city),
-            true => String::new(),
+    //adds each doctor's next free slot for the appointment type, on the given day or in the next
+    //MAX_SLOT_RANGE_DAYS days, dropping those without one on the given day
+    async fn with_next_slots(
+        &self,
+        found: Vec<DoctorPrices>,
+        day: Option<NaiveDate>,
+        soonest: bool,
+    ) -> Result<Vec<FoundDoctor>, AppError> {
+        let now = Utc::now();
+        //each doctor's days to look at, in their zone
+        let mut calendars: HashMap<i64, Calendar> = HashMap::new();
+        for doctor in &found {
+            calendars.entry(doctor.docid).or_insert_with(|| {
+                let tz = zones::parse_stored(&doctor.time_zone);
+                let (from, to) = match day {
+                    Some(day) => (day, day),
+                    None => {
+                        let today = now.with_timezone(&tz).date_naive();
+                        (today, today + ChronoDuration::days(MAX_SLOT_RANGE_DAYS))
+                    }
+                };
+                Calendar {
+                    tz,
+                    from,
+                    to,
+                    hours: Vec::new(),
+                    booked: Vec::new(),
+                }
+            });
+        }
+        //working hours and bookings of all the doctors are read at once
+        let ids: Vec<i64> = calendars.keys().copied().collect();
+        let (Some(first), Some(last)) = (
+            calendars.values().map(|c| c.from).min(),
+            calendars.values().map(|c| c.to).max(),
+        ) else {
+            return Ok(Vec::new());
         };
-        let isapptypespecified = match apptype.is_empty() {
-            false => format!("and t.name = '{}'", apptype),
-            true => String::new(),
+        let hours: Vec<(i32, i16, NaiveTime, NaiveTime)> = sqlx::query_as(
+            "select doctor_id, weekday, start_time, end_time from doctor_schedules
+            where doctor_id = any($1) order by doctor_id, weekday, start_time",
+        )
+        .bind(&ids)
+        .fetch_all(&self.connection)
+        .await?;
+        for (doctor_id, weekday, start_time, end_time) in hours {
+            if let Some(calendar) = calendars.get_mut(&i64::from(doctor_id)) {
+                calendar.hours.push(WorkingHours {
+                    weekday,
+                    start_time,
+                    end_time,
+                });
+            }
+        }
+        let booked: Vec<(i32, DateTime<Utc>, DateTime<Utc>)> = sqlx::query_as(
+            "select doctor_id, date_time, ends_at from appointments
+            where doctor_id = any($1) and status <> 'cancelled' and date_time < $3 and ends_at > $2",
+        )
+        .bind(&ids)
+        .bind(day_start(first - ChronoDuration::days(1)))
+        .bind(day_start(last + ChronoDuration::days(2)))
+        .fetch_all(&self.connection)
+        .await?;
+        for (doctor_id, start, end) in booked {
+            if let Some(calendar) = calendars.get_mut(&i64::from(doctor_id)) {
+                calendar.booked.push((start, end));
+            }
+        }
+        let mut result = Vec::with_capacity(found.len());
+        for mut doctor in found {
+            let calendar = &calendars[&doctor.docid];
+            let length = ChronoDuration::minutes(doctor.duration_minutes.into());
+            let next = slots::free_slots(
+                &calendar.hours,
+                calendar.tz,
+                length,
+                calendar.from,
+                calendar.to,
+                &calendar.booked,
+                now,
+            )
+            .into_iter()
+            .next()
+            .map(|slot| localize_slot(slot, calendar.tz));
+            if day.is_some() && next.is_none() {
+                continue;
+            }
+            if soonest {
+                doctor.sort_value = next.as_ref().map(|slot| slot.start.timestamp() as f64);
+            }
+            result.push(FoundDoctor {
+                doctor,
+                next_available: Some(next),
+            });
+        }
+        if soonest {
+            result.sort_by(|a, b| soonest_order(&a.key(), &b.key()));
+        }
+        Ok(result)
+    }
+
+    //the coordinates the doctor gave, or else those of their address if the geocoder knows it
+    pub async fn doctor_location(
+        &self,
+        latitude: Option<f64>,
+        longitude: Option<f64>,
+        address: &str,
+        city: &str,
+    ) -> Result<Option<Coordinates>, AppError> {
+        match (latitude, longitude) {
+            (Some(lat), Some(lon)) => Ok(Some(Coordinates::new(lat, lon)?)),
+            (None, None) => match self.geocoder.locate(address, city).await {
+                Ok(found) => Ok(found),
+                //a doctor without coordinates is only left out of nearest-doctor searches
+                Err(e) => {
+                    tracing::error!("Could not geocode {}, {}: {}", address, city, e);
+                    Ok(None)
+                }
+            },
+            _ => Err(AppError::Validation(String::from(
+                "latitude and longitude have to be given together",
+            ))),
+        }
+    }
+
+    //appointment types the doctor offers with their prices
+    pub async fn view_offered_appointments(
+        &self,
+        doctor_id: i64,
+    ) -> Result<Vec<AppointmentPrice>, AppError> {
+        let query = sqlx::query_as(
+            "select t.id as appid, t.name, t.description, t.duration_minutes, p.price, p.modes
+            from appointment_prices p
+            join appointment_types t on t.id = p.appointment_type
+            where p.doctor_id = $1 order by t.id",
+        )
+        .bind(doctor_id);
+        self.get_query_result(query).await
+    }
+
+    //offers an appointment type of the doctor's speciality at the price, or changes its price and
+    //the modes it can be booked in
+    pub async fn set_price(
+        &self,
+        doctor_id: i64,
+        apptype: i64,
+        price: i32,
+        modes: Option<&[String]>,
+    ) -> Result<(), AppError> {
+        if price < 0 {
+            return Err(AppError::Validation(String::from(
+                "price can't be negative",
+            )));
+        }
+        if let Some(modes) = modes {
+            if modes.is_empty() {
+                return Err(AppError::Validation(String::from(
+                    "modes needs physical, virtual or both",
+                )));
+            }
+            for mode in modes {
+                check_mode(mode)?;
+            }
+        }
+        let mut tx = self.connection.begin().await?;
+        let row = sqlx::query(
+            "select t.speciality_id = d.speciality_id as matches
+            from appointment_types t, doctors d where t.id = $1 and d.id = $2",
+        )
+        .bind(apptype)
+        .bind(doctor_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No appointment type with ID {}", apptype)))?;
+        if !row.try_get::<bool, _>("matches")? {
+            return Err(AppError::Validation(String::from(
+                "Doctors can only offer appointment types of their own speciality",
+            )));
+        }
+        //locks the price, so concurrent changes are recorded one after the other
+        let old_price: Option<i32> = sqlx::query_scalar(
+            "select price from appointment_prices where doctor_id = $1 and appointment_type = $2 for update",
+        )
+        .bind(doctor_id)
+        .bind(apptype)
+        .fetch_optional(&mut tx)
+        .await?;
+        if old_price == Some(price) && modes.is_none() {
+            return Ok(());
+        }
+        sqlx::query(
+            "insert into appointment_prices(doctor_id, appointment_type, price, modes)
+            values ($1, $2, $3, coalesce($4, array['physical', 'virtual']))
+            on conflict (doctor_id, appointment_type)
+            do update set price = excluded.price, modes = coalesce($4, appointment_prices.modes)",
+        )
+        .bind(doctor_id)
+        .bind(apptype)
+        .bind(price)
+        .bind(modes)
+        .execute(&mut tx)
+        .await?;
+        //only price changes are history, not changed modes
+        if old_price != Some(price) {
+            record_price_change(&mut tx, doctor_id, apptype, old_price, Some(price)).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //stops offering the appointment type; appointments already booked stay as they are
+    pub async fn withdraw_price(&self, doctor_id: i64, apptype: i64) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        let old_price: i32 = sqlx::query_scalar(
+            "delete from appointment_prices where doctor_id = $1 and appointment_type = $2 returning price",
+        )
+        .bind(doctor_id)
+        .bind(apptype)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Appointment type {} is not offered", apptype))
+        })?;
+        record_price_change(&mut tx, doctor_id, apptype, Some(old_price), None).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //newest first
+    pub async fn view_price_history(
+        &self,
+        doctor_id: i64,
+        page: &CursorQuery,
+    ) -> Result<Page<PriceChange>, AppError> {
+        let cursor = Cursor::<PriceChange>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        let query = sqlx::query_as(
+            "select h.id, t.id as appid, t.name as apptype, h.old_price, h.new_price, h.changed_at
+            from appointment_price_history h
+            join appointment_types t on t.id = h.appointment_type
+            where h.doctor_id = $1
+            and ($2::timestamptz is null or (h.changed_at, h.id) < ($2, $3))
+            order by h.changed_at desc, h.id desc limit $4",
+        )
+        .bind(doctor_id)
+        .bind(before)
+        .bind(before_id)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
+    }
+
+    async fn ensure_offered(
+        &self,
+        doctor_id: i64,
+        apptype: i64,
+        phyorvirt: &str,
+    ) -> Result<(), AppError> {
+        let modes: Vec<String> = sqlx::query_scalar(
+            "select modes from appointment_prices where doctor_id = $1 and appointment_type = $2",
+        )
+        .bind(doctor_id)
+        .bind(apptype)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| {
+            AppError::Validation(format!(
+                "This doctor doesn't offer appointment type {}",
+                apptype
+            ))
+        })?;
+        check_mode(phyorvirt)?;
+        if !modes.iter().any(|m| m == phyorvirt) {
+            return Err(AppError::Validation(format!(
+                "This doctor doesn't offer {} appointments of type {}",
+                phyorvirt, apptype
+            )));
+        }
+        Ok(())
+    }
+
+    //public profile with the appointment types the doctor offers and when they are next free
+    pub async fn view_doctor_profile(&self, doctor_id: i64) -> Result<DoctorProfile, AppError> {
+        let info: DoctorProfileRow = sqlx::query_as(
+            "select d.id, d.name, d.speciality_id, s.name as speciality, d.city, d.address, d.phone, d.time_zone,
+            d.bio, d.qualifications, d.years_of_experience, d.languages, d.photo_url, d.latitude, d.longitude,
+            r.rating, coalesce(r.review_count, 0) as review_count
+            from doctors d
+            join specialities s on s.id = d.speciality_id
+            left join doctor_ratings r on r.doctor_id = d.id
+            where d.id = $1",
+        )
+        .bind(doctor_id)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No doctor with ID {}", doctor_id)))?;
+        let offered = self.view_offered_appointments(doctor_id).await?;
+        let tz = zones::parse_stored(&info.time_zone);
+        let hours = self.working_hours(doctor_id).await?;
+        let now = Utc::now();
+        let from = now.with_timezone(&tz).date_naive();
+        let to = from + ChronoDuration::days(MAX_SLOT_RANGE_DAYS);
+        let booked = self.booked_ranges(doctor_id, from, to, None).await?;
+        let appointment_types: Vec<OfferedAppointment> = offered
+            .into_iter()
+            .map(|apptype| {
+                let length = ChronoDuration::minutes(apptype.duration_minutes.into());
+                let next = slots::free_slots(&hours, tz, length, from, to, &booked, now)
+                    .into_iter()
+                    .next()
+                    .map(|slot| localize_slot(slot, tz));
+                OfferedAppointment {
+                    apptype,
+                    next_available: next,
+                }
+            })
+            .collect();
+        let next_available = appointment_types
+            .iter()
+            .filter_map(|t| t.next_available.clone())
+            .min_by_key(|slot| slot.start);
+        Ok(DoctorProfile {
+            info,
+            appointment_types,
+            next_available,
+        })
+    }
+
+    //changes the given fields of the doctor's profile; a new address is geocoded again unless
+    //coordinates are given with it
+    pub async fn update_doctor_profile(
+        &self,
+        doctor_id: i64,
+        update: &ProfileUpdate,
+    ) -> Result<(), AppError> {
+        if update.name.as_deref().is_some_and(|n| n.trim().is_empty()) {
+            return Err(AppError::Validation(String::from("name can't be empty")));
+        }
+        if update
+            .years_of_experience
+            .is_some_and(|y| !(0..=80).contains(&y))
+        {
+            return Err(AppError::Validation(String::from(
+                "years_of_experience must be between 0 and 80",
+            )));
+        }
+        if update
+            .bio
+            .as_deref()
+            .is_some_and(|b| b.chars().count() > MAX_BIO_LENGTH)
+        {
+            return Err(AppError::Validation(format!(
+                "bio can be at most {} characters long",
+                MAX_BIO_LENGTH
+            )));
+        }
+        if let Some(url) = update.photo_url.as_deref().filter(|u| !u.is_empty()) {
+            check_http_url(url)?;
+        }
+        let time_zone = update.time_zone.as_deref().map(zones::parse).transpose()?;
+        let qualifications = update
+            .qualifications
+            .as_deref()
+            .map(|q| profile_list("qualifications", q))
+            .transpose()?;
+        let languages = update
+            .languages
+            .as_deref()
+            .map(|l| profile_list("languages", l))
+            .transpose()?;
+        let current: DoctorAddress =
+            sqlx::query_as("select id, address, city from doctors where id = $1")
+                .bind(doctor_id)
+                .fetch_optional(&self.connection)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("No doctor with ID {}", doctor_id)))?;
+        let moved = update.address.is_some() || update.city.is_some();
+        //None keeps the coordinates, Some(None) removes them
+        let location = if update.latitude.is_some() || update.longitude.is_some() || moved {
+            Some(
+                self.doctor_location(
+                    update.latitude,
+                    update.longitude,
+                    update.address.as_deref().unwrap_or(&current.address),
+                    update.city.as_deref().unwrap_or(&current.city),
+                )
+                .await?,
+            )
+        } else {
+            None
         };
+        sqlx::query(
+            "update doctors set
+            name = coalesce($2, name),
+            city = coalesce($3, city),
+            address = coalesce($4, address),
+            phone = coalesce($5, phone),
+            time_zone = coalesce($6, time_zone),
+            bio = case when $7::text is null then bio else nullif(trim($7), '') end,
+            qualifications = coalesce($8, qualifications),
+            years_of_experience = coalesce($9, years_of_experience),
+            languages = coalesce($10, languages),
+            photo_url = case when $11::text is null then photo_url else nullif($11, '') end,
+            latitude = case when $12 then $13 else latitude end,
+            longitude = case when $12 then $14 else longitude end
+            where id = $1",
+        )
+        .bind(doctor_id)
+        .bind(update.name.as_deref().map(str::trim))
+        .bind(&update.city)
+        .bind(&update.address)
+        .bind(&update.phone)
+        .bind(time_zone.map(|tz| tz.name()))
+        .bind(&update.bio)
+        .bind(qualifications)
+        .bind(update.years_of_experience)
+        .bind(languages)
+        .bind(&update.photo_url)
+        .bind(location.is_some())
+        .bind(location.flatten().map(|c| c.latitude))
+        .bind(location.flatten().map(|c| c.longitude))
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
 
-        let query = format!(
-            "
-                    select d.id as docid, d.name as docname, d.city as city, d.address as address, t.name as apptype, t.id as appid, p.price
-                    from doctors d
-                    join appointment_types t on d.speciality_id = t.speciality_id
-                    join appointment_prices p on d.id = p.doctor_id and t.id = p.appointment_type
-                    where 1=1 {} {};
-                    ",
-            isapptypespecified, iscityspecified
+    //looks up the coordinates of every doctor that has none, returns how many were found
+    pub async fn geocode_doctors(&self) -> Result<u64, AppError> {
+        let query = sqlx::query_as(
+            "select id, address, city from doctors where latitude is null order by id",
         );
-        self.get_query_result::<DoctorPrices, Postgres>(&query)
-            .await
+        let doctors: Vec<DoctorAddress> = self.get_query_result(query).await?;
+        let mut found = 0;
+        for doctor in doctors {
+            let Some(at) = self
+                .doctor_location(None, None, &doctor.address, &doctor.city)
+                .await?
+            else {
+                tracing::info!("No coordinates found for doctor ID {}", doctor.id);
+                continue;
+            };
+            sqlx::query("update doctors set latitude = $2, longitude = $3 where id = $1")
+                .bind(doctor.id)
+                .bind(at.latitude)
+                .bind(at.longitude)
+                .execute(&self.connection)
+                .await?;
+            found += 1;
+        }
+        Ok(found)
     }
 
-    pub async fn view_specialities(&self) -> Vec<Specialities> {
-        let query = String::from(
+    pub async fn view_specialities(&self) -> Result<Vec<Specialities>, AppError> {
+        let query = sqlx::query_as(
             "select id, name, description as desc
                     from specialities;",
         );
-        self.get_query_result::<Specialities, Postgres>(&query)
-            .await
+        self.get_query_result(query).await
     }
 
-    pub async fn view_appointment_types(&self) -> Vec<Apptypes> {
-        let query = String::from(
-            "select id, name from appointment_types;"
-        );
-        self.get_query_result::<Apptypes, Postgres>(&query)
-            .await
+    pub async fn view_appointment_types(&self) -> Result<Vec<Apptypes>, AppError> {
+        let query = sqlx::query_as("select id, name from appointment_types;");
+        self.get_query_result(query).await
     }
 
-    pub async fn view_cities(&self) -> Vec<Cities> {
-        let query = String::from(
-            "select distinct(city) as city from doctors"
-        );
-        self.get_query_result::<Cities, Postgres>(&query)
-            .await
+    //alphabetical
+    pub async fn view_cities(&self, page: &CursorQuery) -> Result<Page<Cities>, AppError> {
+        let cursor = Cursor::<Cities>::parse(page)?;
+        let query = sqlx::query_as(
+            "select distinct(city) as city from doctors where $1::text is null or city > $1
+            order by city limit $2",
+        )
+        .bind(cursor.after.clone())
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
     }
 
-    pub async fn view_doctor_appointments(&self, doctor_id: i64) -> Vec<DoctorAppointments> {
-        let query = format!(
-            "select id, patient_id, appointment_type as apptype,
-            TO_CHAR(date_time, 'YYYY-MM-DD HH24:MM:SS') as datetime,
-            type as phyorvirt, status, prescription from appointments where doctor_id = {} order by date_time
-            ", doctor_id
-        );
-        self.get_query_result::<DoctorAppointments, Postgres>(&query)
-            .await
+    //oldest first
+    pub async fn view_doctor_appointments(
+        &self,
+        doctor_id: i64,
+        page: &CursorQuery,
+    ) -> Result<Page<DoctorAppointments>, AppError> {
+        let cursor = Cursor::<DoctorAppointments>::parse(page)?;
+        let (after, after_id) = cursor.after.unzip();
+        let tz = self.doctor_zone(doctor_id).await?;
+        let query = sqlx::query_as(
+            "select id, patient_id, appointment_type as apptype, date_time as datetime,
+            type as phyorvirt, status, prescription from appointments where doctor_id = $1
+            and ($2::timestamptz is null or (date_time, id) > ($2, $3))
+            order by date_time, id limit $4
+            ",
+        )
+        .bind(doctor_id)
+        .bind(after)
+        .bind(after_id)
+        .bind(cursor.fetch_limit());
+        let mut appointments = self.get_page(query, &cursor).await?;
+        for a in &mut appointments.items {
+            a.datetime = zones::localize(&a.datetime, tz);
+        }
+        Ok(appointments)
     }
 
-    pub async fn register(&self, email: &String, password: &String, isdoctor: bool) -> bool {
-        let Ok((hash, salt)) = argon_hash_password::create_hash_and_salt(&password) else {
-            tracing::error!("Hash and salt were not able to be created, registration error");
-            return false;
+    pub async fn view_schedule(&self, doctor_id: i64) -> Result<Vec<WorkingHours>, AppError> {
+        self.ensure_doctor_exists(doctor_id).await?;
+        self.working_hours(doctor_id).await
+    }
+
+    //the doctor's weekly working hours, for callers that have already looked the doctor up
+    async fn working_hours(&self, doctor_id: i64) -> Result<Vec<WorkingHours>, AppError> {
+        let query = sqlx::query_as(
+            "select weekday, start_time, end_time from doctor_schedules where doctor_id = $1 order by weekday, start_time",
+        )
+        .bind(doctor_id);
+        self.get_query_result(query).await
+    }
+
+    //replaces all of the doctor's weekly working hours
+    pub async fn set_schedule(
+        &self,
+        doctor_id: i64,
+        hours: &[WorkingHours],
+    ) -> Result<(), AppError> {
+        if hours.iter().any(|h| !(1..=7).contains(&h.weekday)) {
+            return Err(AppError::Validation(String::from(
+                "weekday must be between 1 (Monday) and 7 (Sunday)",
+            )));
+        }
+        if hours.iter().any(|h| h.start_time >= h.end_time) {
+            return Err(AppError::Validation(String::from(
+                "start_time must be before end_time",
+            )));
+        }
+        if slots::hours_overlap(hours) {
+            return Err(AppError::Validation(String::from(
+                "working hours on the same weekday must not overlap",
+            )));
+        }
+        let mut tx = self.connection.begin().await?;
+        sqlx::query("delete from doctor_schedules where doctor_id = $1")
+            .bind(doctor_id)
+            .execute(&mut tx)
+            .await?;
+        for h in hours {
+            sqlx::query(
+                "insert into doctor_schedules(doctor_id, weekday, start_time, end_time) values ($1, $2, $3, $4)",
+            )
+            .bind(doctor_id)
+            .bind(h.weekday)
+            .bind(h.start_time)
+            .bind(h.end_time)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //free slots of a doctor for an appointment type between two dates (inclusive) of the doctor's
+    //zone, shown in the zone `show_in` if given and the doctor's zone otherwise
+    pub async fn view_free_slots(
+        &self,
+        doctor_id: i64,
+        apptype: i64,
+        from: NaiveDate,
+        to: NaiveDate,
+        show_in: Option<&str>,
+    ) -> Result<Vec<Slot>, AppError> {
+        if from > to {
+            return Err(AppError::Validation(String::from(
+                "from must not be after to",
+            )));
+        }
+        if to - from > ChronoDuration::days(MAX_SLOT_RANGE_DAYS) {
+            return Err(AppError::Validation(format!(
+                "at most {} days of slots can be requested at once",
+                MAX_SLOT_RANGE_DAYS
+            )));
+        }
+        let display_tz = show_in.map(zones::parse).transpose()?;
+        let tz = self.doctor_zone(doctor_id).await?;
+        let hours = self.working_hours(doctor_id).await?;
+        let length = self.slot_length(apptype).await?;
+        let booked = self.booked_ranges(doctor_id, from, to, None).await?;
+        let display_tz = display_tz.unwrap_or(tz);
+        Ok(
+            slots::free_slots(&hours, tz, length, from, to, &booked, Utc::now())
+                .into_iter()
+                .map(|slot| localize_slot(slot, display_tz))
+                .collect(),
+        )
+    }
+
+    async fn ensure_doctor_exists(&self, doctor_id: i64) -> Result<(), AppError> {
+        self.doctor_zone(doctor_id).await?;
+        Ok(())
+    }
+
+    async fn doctor_zone(&self, doctor_id: i64) -> Result<Tz, AppError> {
+        let row = sqlx::query("select time_zone from doctors where id = $1")
+            .bind(doctor_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No doctor with ID {}", doctor_id)))?;
+        Ok(zones::parse_stored(row.try_get("time_zone")?))
+    }
+
+    async fn patient_zone(&self, patient_id: i64) -> Result<Tz, AppError> {
+        let row = sqlx::query("select time_zone from patients where id = $1")
+            .bind(patient_id)
+            .fetch_optional(&self.connection)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No patient with ID {}", patient_id)))?;
+        Ok(zones::parse_stored(row.try_get("time_zone")?))
+    }
+
+    //an appointment type's duration is also the length of its slots
+    async fn slot_length(&self, apptype: i64) -> Result<ChronoDuration, AppError> {
+        let row = sqlx::query("select duration_minutes from appointment_types where id = $1")
+            .bind(apptype)
+            .fetch_optional(&self.connection)
+            .await?
+            .ok_or_else(|| {
+                AppError::NotFound(format!("No appointment type with ID {}", apptype))
+            })?;
+        let minutes: i32 = row.try_get("duration_minutes")?;
+        Ok(ChronoDuration::minutes(minutes.into()))
+    }
+
+    //time taken up by the doctor's non cancelled appointments around the given days; the days are
+    //widened by one on both ends since no zone is more than a day away from UTC
+    //optionally leaves out one appointment, so it doesn't block itself while being rescheduled
+    async fn booked_ranges(
+        &self,
+        doctor_id: i64,
+        from: NaiveDate,
+        to: NaiveDate,
+        except: Option<i64>,
+    ) -> Result<Vec<TimeRange>, AppError> {
+        Ok(sqlx::query_as(
+            "select date_time, ends_at from appointments
+            where doctor_id = $1 and status <> 'cancelled' and date_time < $3 and ends_at > $2
+            and ($4::bigint is null or id <> $4)",
+        )
+        .bind(doctor_id)
+        .bind(day_start(from - ChronoDuration::days(1)))
+        .bind(day_start(to + ChronoDuration::days(2)))
+        .bind(except)
+        .fetch_all(&self.connection)
+        .await?)
+    }
+
+    //checks that an appointment of the given type can start at `start` and returns its length
+    async fn check_bookable(
+        &self,
+        docid: i64,
+        apptype: i64,
+        start: DateTime<Utc>,
+        except: Option<i64>,
+    ) -> Result<ChronoDuration, AppError> {
+        let tz = self.doctor_zone(docid).await?;
+        let day = start.with_timezone(&tz).date_naive();
+        let hours = self.working_hours(docid).await?;
+        let length = self.slot_length(apptype).await?;
+        let booked = self.booked_ranges(docid, day, day, except).await?;
+        let now = Utc::now();
+        let is_slot = |booked: &[TimeRange]| {
+            slots::free_slots(&hours, tz, length, day, day, booked, now)
+                .iter()
+                .any(|&(slot, _)| slot == start)
         };
-        let query = format!(
-            "
-                    insert into login(email, password, salt, isdoctor) values ('{}', '{}', '{}', {})
-                            ",
-            email, hash, salt, isdoctor
-        );
-        match sqlx::query(&query).execute(&self.connection).await {
-            Ok(_) => return true,
-            Err(_) => return false,
+        if !is_slot(&booked) {
+            if is_slot(&[]) {
+                tracing::error!("Appointment has already been booked");
+                return Err(AppError::Conflict {
+                    message: String::from("Appointment has already been booked"),
+                    details: None,
+                });
+            }
+            return Err(AppError::Validation(String::from(
+                "datetime is not a bookable slot of this doctor",
+            )));
         }
+        Ok(length)
     }
 
-    pub async fn add_new_patient(&self, name: &String, email: &String, phone: &String) -> bool {
-        let query = format!(
-            "
-                    insert into patients(name, email, phone) values ('{}','{}','{}');
-                            ",
-            name, email, phone
-        );
-        match sqlx::query(&query).execute(&self.connection).await {
-            Ok(_) => return true,
-            Err(_) => return false,
+    //moves appointments that ended before the cutoff into patients_previous_appointments,
+    //in one statement so concurrent runs on several servers can't copy a row twice
+    pub async fn archive_appointments(&self, cutoff: DateTime<Utc>) -> Result<u64, AppError> {
+        let mut conn = self.connection.acquire().await?;
+        archive_finished(&mut conn, cutoff).await
+    }
+
+    //sends a reminder to the patient of every scheduled appointment starting within the next
+    //`offset_minutes`, unless it was already sent or the appointment is already close enough for
+    //the next smaller offset `next_offset_minutes`; returns how many were sent
+    pub async fn send_due_reminders(
+        &self,
+        offset_minutes: i32,
+        next_offset_minutes: i32,
+    ) -> Result<u64, AppError> {
+        let mut tx = self.connection.begin().await?;
+        //the primary key makes sure only one server claims each reminder
+        let due = sqlx::query(
+            "insert into appointment_reminders(appointment_id, offset_minutes)
+            select id, $1 from only appointments
+            where status = 'scheduled'
+            and date_time <= now() + make_interval(mins => $1)
+            and date_time > now() + make_interval(mins => $2)
+            on conflict do nothing
+            returning appointment_id",
+        )
+        .bind(offset_minutes)
+        .bind(next_offset_minutes)
+        .fetch_all(&mut tx)
+        .await?;
+        for row in &due {
+            let app = fetch_appointment(&mut tx, row.try_get("appointment_id")?).await?;
+            let message = format!(
+                "Reminder: appointment with {} on {}",
+                app.docname,
+                zones::display(&app.date_time, zones::parse_stored(&app.patient_zone))
+            );
+            self.notify(&mut tx, Some(app.patient_id), None, &message)
+                .await?;
         }
+        tx.commit().await?;
+        Ok(due.len() as u64)
     }
 
-    pub async fn add_new_doctor(
+    //part of adding a patient or doctor, in their transaction
+    async fn add_login(
         &self,
-        name: &String,
-        speciality: i64,
-        city: &String,
-        address: &String,
-        email: &String,
-        phone: &String,
-    ) -> bool {
-        let query = format!("
-                    insert into doctors(name, speciality_id, city, address, email, phone) values ('{}',{},'{}', '{}', '{}', '{}');
-                            ", name, speciality, city, address, email, phone);
-        match sqlx::query(&query).execute(&self.connection).await {
-            Ok(_) => return true,
-            Err(_) => return false,
+        conn: &mut PgConnection,
+        email: &str,
+        password: &str,
+        isdoctor: bool,
+    ) -> Result<(), AppError> {
+        let Ok((hash, salt)) = argon_hash_password::create_hash_and_salt(password) else {
+            tracing::error!("Hash and salt were not able to be created, registration error");
+            return Err(AppError::Internal(String::from("Could not hash password")));
+        };
+        sqlx::query("insert into login(email, password, salt, isdoctor) values ($1, $2, $3, $4)")
+            .bind(email)
+            .bind(hash)
+            .bind(salt)
+            .bind(isdoctor)
+            .execute(&mut *conn)
+            .await?;
+        if self.channels.contains(&Channel::Email) {
+            enqueue(
+                conn,
+                Channel::Email,
+                email,
+                "Welcome to Excalibur",
+                "Your account has been created, you can now log in with this email address.",
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    //notifications go to exactly one of a patient or a doctor, and are also queued for delivery
+    //on every enabled channel
+    async fn notify(
+        &self,
+        conn: &mut PgConnection,
+        patient_id: Option<i32>,
+        doctor_id: Option<i32>,
+        message: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "insert into notifications(patient_id, doctor_id, message, date_time) values ($1, $2, $3, now())",
+        )
+        .bind(patient_id)
+        .bind(doctor_id)
+        .bind(message)
+        .execute(&mut *conn)
+        .await?;
+        if self.channels.is_empty() {
+            return Ok(());
+        }
+        let contact = sqlx::query(
+            "select email, phone from patients where id = $1
+            union all select email, phone from doctors where id = $2",
+        )
+        .bind(patient_id)
+        .bind(doctor_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        for &channel in &self.channels {
+            let recipient: String = match channel {
+                Channel::Email => contact.try_get("email")?,
+                Channel::Sms => contact.try_get("phone")?,
+            };
+            enqueue(conn, channel, &recipient, NOTIFICATION_SUBJECT, message).await?;
         }
+        Ok(())
     }
 
+    //takes due outbox messages for OUTBOX_LEASE_SECS; if the server dies while sending, they
+    //become due again afterwards, so a message can be sent twice but never gets lost
+    pub async fn claim_outbox(&self, batch: i64) -> Result<Vec<OutboxMessage>, AppError> {
+        let query = sqlx::query_as(
+            "update outbox set next_attempt_at = now() + make_interval(secs => $2)
+            where id in (
+                select id from outbox where status = 'pending' and next_attempt_at <= now()
+                order by next_attempt_at limit $1 for update skip locked
+            )
+            returning id, channel, recipient, subject, body, attempts",
+        )
+        .bind(batch)
+        .bind(OUTBOX_LEASE_SECS);
+        self.get_query_result(query).await
+    }
+
+    pub async fn outbox_sent(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query(
+            "update outbox set status = 'sent', attempts = attempts + 1, sent_at = now(), last_error = null where id = $1",
+        )
+        .bind(id)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+
+    //retries at `retry_at`, or gives up on the message if there is none
+    pub async fn outbox_failed(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "update outbox set attempts = attempts + 1, last_error = $2,
+            status = case when $3::timestamptz is null then 'failed' else 'pending' end,
+            next_attempt_at = coalesce($3, next_attempt_at)
+            where id = $1",
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_at)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+
+    //the patient and their login are added together, so a failed signup leaves neither behind
+    pub async fn add_patient_with_login(
+        &self,
+        patient: &Patient,
+        time_zone: Tz,
+    ) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        sqlx::query("insert into patients(name, email, phone, time_zone) values ($1, $2, $3, $4);")
+            .bind(&patient.name)
+            .bind(&patient.email)
+            .bind(&patient.phone)
+            .bind(time_zone.name())
+            .execute(&mut tx)
+            .await?;
+        self.add_login(&mut tx, &patient.email, &patient.password, false)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //like add_patient_with_login
+    pub async fn add_doctor_with_login(
+        &self,
+        doctor: &Doctor,
+        time_zone: Tz,
+        location: Option<Coordinates>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        self.add_login(&mut tx, &doctor.email, &doctor.password, true)
+            .await?;
+        sqlx::query("
+                    insert into doctors(name, speciality_id, city, address, email, phone, time_zone, latitude, longitude) values ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+                            ")
+            .bind(&doctor.name)
+            .bind(doctor.speciality)
+            .bind(&doctor.city)
+            .bind(&doctor.address)
+            .bind(&doctor.email)
+            .bind(&doctor.phone)
+            .bind(time_zone.name())
+            .bind(location.map(|c| c.latitude))
+            .bind(location.map(|c| c.longitude))
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //new appointments always start out scheduled and without a prescription
     pub async fn add_new_appointment(
         &self,
         docid: i64,
         patid: i64,
         apptype: i64,
-        datetime: &String,
-        phyorvirt: &String,
-        status: &String,
-        prescription: &String,
-    ) -> bool {
-        let Ok(naivedatetime) = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S") else {
-            tracing::error!("Couldn't parse date time into NaiveDateTime");
-            return false;
+        start: DateTime<Utc>,
+        phyorvirt: &str,
+    ) -> Result<(), AppError> {
+        self.ensure_offered(docid, apptype, phyorvirt).await?;
+        let length = self.check_bookable(docid, apptype, start, None).await?;
+        let mut tx = self.connection.begin().await?;
+        //the check above only gives nicer errors; two concurrent bookings of the same slot are
+        //stopped by the no_overlapping_appointments constraint, which fails the insert with a conflict
+        let row = sqlx::query("
+                    insert into appointments (doctor_id, patient_id, appointment_type, date_time, ends_at, type, status) values ($1, $2, $3, $4, $5, $6, $7) returning id
+                            ")
+            .bind(docid)
+            .bind(patid)
+            .bind(apptype)
+            .bind(start)
+            .bind(start + length)
+            .bind(phyorvirt)
+            .bind(AppointmentStatus::Scheduled.as_str())
+            .fetch_one(&mut tx)
+            .await?;
+        let app = fetch_appointment(&mut tx, row.try_get("id")?).await?;
+        let message = format!(
+            "New appointment with {} on {}",
+            app.patname,
+            zones::display(&app.date_time, zones::parse_stored(&app.doctor_zone))
+        );
+        self.notify(&mut tx, None, Some(app.doctor_id), &message)
+            .await?;
+        events::publish(&mut tx, &AppointmentEvent::new("appointment.created", &app)).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn cancel_appointment(
+        &self,
+        docid: i64,
+        patid: i64,
+        start: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        let id: i64 = sqlx::query_scalar("
+                    update only appointments set status = 'cancelled' where doctor_id = $1 and patient_id = $2 and date_time = $3 and status = 'scheduled' returning id;
+                            ")
+            .bind(docid)
+            .bind(patid)
+            .bind(start)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or_else(|| {
+                AppError::NotFound(String::from(
+                    "No scheduled appointment with this doctor at this time",
+                ))
+            })?;
+        self.announce_cancellation(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //like cancel_appointment, for the patient's appointment with the given ID
+    pub async fn cancel_appointment_by_id(
+        &self,
+        appointment_id: i64,
+        patid: i64,
+    ) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        let id: i64 = sqlx::query_scalar(
+            "update only appointments set status = 'cancelled'
+            where id = $1 and patient_id = $2 and status = 'scheduled' returning id",
+        )
+        .bind(appointment_id)
+        .bind(patid)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "You have no scheduled appointment with ID {}",
+                appointment_id
+            ))
+        })?;
+        self.announce_cancellation(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //tells the doctor and event subscribers about an appointment just cancelled
+    async fn announce_cancellation(
+        &self,
+        conn: &mut PgConnection,
+        appointment_id: i64,
+    ) -> Result<(), AppError> {
+        let app = fetch_appointment(&mut *conn, appointment_id).await?;
+        let message = format!(
+            "Appointment with {} on {} was cancelled",
+            app.patname,
+            zones::display(&app.date_time, zones::parse_stored(&app.doctor_zone))
+        );
+        self.notify(&mut *conn, None, Some(app.doctor_id), &message)
+            .await?;
+        events::publish(conn, &AppointmentEvent::new("appointment.cancelled", &app)).await
+    }
+
+    //moves an appointment of the doctor along its lifecycle (see status.rs); the prescription
+    //can only be written by the appointment's doctor when fulfilling it
+    pub async fn update_appointment_status(
+        &self,
+        appointment_id: i64,
+        doctor_id: i64,
+        next: AppointmentStatus,
+        prescription: Option<&str>,
+    ) -> Result<(), AppError> {
+        //cancelling goes through cancel_appointment, which notifies the doctor and sends
+        //appointment.cancelled
+        if next == AppointmentStatus::Cancelled {
+            return Err(AppError::Validation(String::from(
+                "Appointments can't be cancelled here, status must be in_progress, fulfilled or no_show",
+            )));
+        }
+        if prescription.is_some() && next != AppointmentStatus::Fulfilled {
+            return Err(AppError::Validation(String::from(
+                "A prescription can only be written when fulfilling an appointment",
+            )));
+        }
+        let mut tx = self.connection.begin().await?;
+        let row = sqlx::query(
+            "select status from only appointments where id = $1 and doctor_id = $2 for update",
+        )
+        .bind(appointment_id)
+        .bind(doctor_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("No such appointment")))?;
+        let status: String = row.try_get("status")?;
+        let current: AppointmentStatus = status.parse()?;
+        if !current.can_become(next) {
+            return Err(AppError::Conflict {
+                message: format!("Appointment can't go from {} to {}", current, next),
+                details: None,
+            });
+        }
+        sqlx::query(
+            "update only appointments set status = $2, prescription = coalesce($3, prescription) where id = $1",
+        )
+        .bind(appointment_id)
+        .bind(next.as_str())
+        .bind(prescription)
+        .execute(&mut tx)
+        .await?;
+        let app = fetch_appointment(&mut tx, appointment_id).await?;
+        events::publish(
+            &mut tx,
+            &AppointmentEvent::new("appointment.status_changed", &app),
+        )
+        .await?;
+        if prescription.is_some() {
+            events::publish(&mut tx, &AppointmentEvent::new("prescription.issued", &app)).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //moves a scheduled appointment of the user to a new free slot, keeping the old time in
+    //appointment_reschedules and notifying the other party
+    pub async fn reschedule_appointment(
+        &self,
+        appointment_id: i64,
+        user_id: i64,
+        isdoctor: bool,
+        new_start: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.connection.begin().await?;
+        let app = sqlx::query_as::<_, AppointmentRow>(&format!(
+            "{} where a.id = $1 and (case when $3 then a.doctor_id else a.patient_id end) = $2 for update of a",
+            APPOINTMENT_ROW_SELECT
+        ))
+        .bind(appointment_id)
+        .bind(user_id)
+        .bind(isdoctor)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("No such appointment")))?;
+        if app.status.parse::<AppointmentStatus>()? != AppointmentStatus::Scheduled {
+            return Err(AppError::Validation(format!(
+                "Only scheduled appointments can be rescheduled, this one is {}",
+                app.status
+            )));
+        }
+        let length = self
+            .check_bookable(
+                app.doctor_id.into(),
+                app.appointment_type.into(),
+                new_start,
+                Some(app.id),
+            )
+            .await?;
+        sqlx::query(
+            "insert into appointment_reschedules(appointment_id, old_date_time, new_date_time, by_doctor) values ($1, $2, $3, $4)",
+        )
+        .bind(app.id)
+        .bind(app.date_time)
+        .bind(new_start)
+        .bind(isdoctor)
+        .execute(&mut tx)
+        .await?;
+        //reminders for the old time don't count for the new one
+        sqlx::query("delete from appointment_reminders where appointment_id = $1")
+            .bind(app.id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("update only appointments set date_time = $2, ends_at = $3 where id = $1")
+            .bind(app.id)
+            .bind(new_start)
+            .bind(new_start + length)
+            .execute(&mut tx)
+            .await?;
+        //times in the message are in the zone of whoever receives it
+        let (message, patient, doctor) = if isdoctor {
+            let tz = zones::parse_stored(&app.patient_zone);
+            (
+                format!(
+                    "Your appointment with {} on {} was moved to {}",
+                    app.docname,
+                    zones::display(&app.date_time, tz),
+                    zones::display(&new_start, tz)
+                ),
+                Some(app.patient_id),
+                None,
+            )
+        } else {
+            let tz = zones::parse_stored(&app.doctor_zone);
+            (
+                format!(
+                    "Appointment with {} on {} was moved to {}",
+                    app.patname,
+                    zones::display(&app.date_time, tz),
+                    zones::display(&new_start, tz)
+                ),
+                None,
+                Some(app.doctor_id),
+            )
         };
-        let doctorapps = self.view_doctor_appointments(docid).await;
-        for app in doctorapps.iter() {
-            if app.datetime == *datetime && app.status != "cancelled" {
-                tracing::error!("Appointment has already been booked");
-                return false;
-            }
+        self.notify(&mut tx, patient, doctor, &message).await?;
+        let moved = fetch_appointment(&mut tx, app.id).await?;
+        events::publish(
+            &mut tx,
+            &AppointmentEvent::new("appointment.rescheduled", &moved),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //archived appointments can be reviewed too, so this looks at all appointments
+    pub async fn add_review(
+        &self,
+        patient_id: i64,
+        appointment_id: i64,
+        rating: i16,
+        comment: Option<&str>,
+    ) -> Result<(), AppError> {
+        if !(1..=5).contains(&rating) {
+            return Err(AppError::Validation(String::from(
+                "rating must be between 1 and 5",
+            )));
+        }
+        let comment = comment.map(str::trim).filter(|c| !c.is_empty());
+        check_review_length(comment)?;
+        let mut tx = self.connection.begin().await?;
+        let row = sqlx::query(
+            "select a.doctor_id, a.status, p.name as patname
+            from appointments a join patients p on p.id = a.patient_id
+            where a.id = $1 and a.patient_id = $2",
+        )
+        .bind(appointment_id)
+        .bind(patient_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("No such appointment")))?;
+        let status: String = row.try_get("status")?;
+        if status.parse::<AppointmentStatus>()? != AppointmentStatus::Fulfilled {
+            return Err(AppError::Validation(format!(
+                "Only fulfilled appointments can be reviewed, this one is {}",
+                status
+            )));
         }
-        let query = format!("
-                    insert into appointments (doctor_id, patient_id, appointment_type, date_time, type, status, prescription) values ({},{},{},'{}','{}','{}', '{}')
-                            ", docid, patid, apptype, naivedatetime, phyorvirt, status, prescription);
-        match sqlx::query(&query).execute(&self.connection).await {
-            Ok(_) => return true,
-            Err(_) => return false,
+        let doctor_id: i32 = row.try_get("doctor_id")?;
+        //a second review of the same appointment fails on the unique appointment_id with a conflict
+        sqlx::query(
+            "insert into reviews(appointment_id, doctor_id, patient_id, rating, comment) values ($1, $2, $3, $4, $5)",
+        )
+        .bind(appointment_id)
+        .bind(doctor_id)
+        .bind(patient_id)
+        .bind(rating)
+        .bind(comment)
+        .execute(&mut tx)
+        .await?;
+        let patname: String = row.try_get("patname")?;
+        let message = format!("New {}-star review from {}", rating, patname);
+        self.notify(&mut tx, None, Some(doctor_id), &message)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    //visible reviews of the doctor, newest first
+    pub async fn view_doctor_reviews(
+        &self,
+        doctor_id: i64,
+        page: &CursorQuery,
+    ) -> Result<Page<Review>, AppError> {
+        let cursor = Cursor::<Review>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        self.ensure_doctor_exists(doctor_id).await?;
+        let query = sqlx::query_as(
+            "select id, rating, comment, reply, created_at, replied_at from reviews
+            where doctor_id = $1 and not hidden
+            and ($2::timestamptz is null or (created_at, id) < ($2, $3))
+            order by created_at desc, id desc limit $4",
+        )
+        .bind(doctor_id)
+        .bind(before)
+        .bind(before_id)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
+    }
+
+    //replying again replaces the earlier reply
+    pub async fn reply_to_review(
+        &self,
+        review_id: i64,
+        doctor_id: i64,
+        reply: &str,
+    ) -> Result<(), AppError> {
+        let reply = reply.trim();
+        if reply.is_empty() {
+            return Err(AppError::Validation(String::from("reply can't be empty")));
         }
+        check_review_length(Some(reply))?;
+        let res = sqlx::query(
+            "update reviews set reply = $3, replied_at = now() where id = $1 and doctor_id = $2",
+        )
+        .bind(review_id)
+        .bind(doctor_id)
+        .bind(reply)
+        .execute(&self.connection)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AppError::NotFound(String::from("No such review")));
+        }
+        Ok(())
     }
 
-    pub async fn cancel_appointment(&self, docid: i64, patid: i64, datetime: &String) -> bool {
-        let query = format!("
-                    update appointments set status = 'cancelled' where doctor_id = {} and patient_id = {} and TO_CHAR(date_time, 'YYYY-MM-DD HH24:MI:SS') = '{}';
-                            ", docid, patid, datetime);
-        match sqlx::query(&query).execute(&self.connection).await {
-            Ok(_) => true,
-            Err(_) => false,
+    //hides a review from everyone but admins, or shows it again
+    pub async fn moderate_review(
+        &self,
+        review_id: i64,
+        hidden: bool,
+        reason: Option<&str>,
+    ) -> Result<(), AppError> {
+        let res = sqlx::query(
+            "update reviews set hidden = $2, hidden_reason = case when $2 then $3 end where id = $1",
+        )
+        .bind(review_id)
+        .bind(hidden)
+        .bind(reason)
+        .execute(&self.connection)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AppError::NotFound(String::from("No such review")));
         }
+        Ok(())
+    }
+
+    //every review for admins, newest first
+    pub async fn view_reviews_for_moderation(
+        &self,
+        hidden: Option<bool>,
+        page: &CursorQuery,
+    ) -> Result<Page<ModeratedReview>, AppError> {
+        let cursor = Cursor::<ModeratedReview>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        let query = sqlx::query_as(
+            "select id, appointment_id, doctor_id, patient_id, rating, comment, reply, hidden, hidden_reason, created_at
+            from reviews where ($1::boolean is null or hidden = $1)
+            and ($2::timestamptz is null or (created_at, id) < ($2, $3))
+            order by created_at desc, id desc limit $4",
+        )
+        .bind(hidden)
+        .bind(before)
+        .bind(before_id)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
+    }
+
+    //notifications of the user, newest first, with times in the user's zone
+    pub async fn view_notifications(
+        &self,
+        user_id: i64,
+        isdoctor: bool,
+        unread_only: bool,
+        page: &CursorQuery,
+    ) -> Result<Page<Notification>, AppError> {
+        let cursor = Cursor::<Notification>::parse(page)?;
+        let (before, before_id) = cursor.after.unzip();
+        let tz = if isdoctor {
+            self.doctor_zone(user_id).await?
+        } else {
+            self.patient_zone(user_id).await?
+        };
+        let query = sqlx::query_as(
+            "select id, message, date_time as datetime, read_at is not null as read
+            from notifications
+            where (case when $2 then doctor_id else patient_id end) = $1 and (not $3 or read_at is null)
+            and ($4::timestamptz is null or (date_time, id) < ($4, $5))
+            order by date_time desc, id desc
+            limit $6",
+        )
+        .bind(user_id)
+        .bind(isdoctor)
+        .bind(unread_only)
+        .bind(before)
+        .bind(before_id)
+        .bind(cursor.fetch_limit());
+        let mut notifications = self.get_page(query, &cursor).await?;
+        for n in &mut notifications.items {
+            n.datetime = zones::localize(&n.datetime, tz);
+        }
+        Ok(notifications)
+    }
+
+    //marking an already read notification again keeps the time it was first read
+    pub async fn mark_notification_read(
+        &self,
+        notification_id: i64,
+        user_id: i64,
+        isdoctor: bool,
+    ) -> Result<(), AppError> {
+        let res = sqlx::query(
+            "update notifications set read_at = coalesce(read_at, now())
+            where id = $1 and (case when $3 then doctor_id else patient_id end) = $2",
+        )
+        .bind(notification_id)
+        .bind(user_id)
+        .bind(isdoctor)
+        .execute(&self.connection)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AppError::NotFound(String::from("No such notification")));
+        }
+        Ok(())
+    }
+
+    //owner is the doctor the webhook belongs to, or None for an admin webhook that gets the
+    //events of every doctor
+    pub async fn create_webhook(
+        &self,
+        owner: Option<i64>,
+        url: &str,
+        events: &[String],
+    ) -> Result<CreatedWebhook, AppError> {
+        webhooks::check_url(url).await?;
+        if events.is_empty() {
+            return Err(AppError::Validation(String::from(
+                "A webhook needs at least one event",
+            )));
+        }
+        if let Some(unknown) = events
+            .iter()
+            .find(|e| !webhooks::EVENTS.contains(&e.as_str()))
+        {
+            return Err(AppError::Validation(format!(
+                "Unknown event {}, expected one of {}",
+                unknown,
+                webhooks::EVENTS.join(", ")
+            )));
+        }
+        let mut events = events.to_vec();
+        events.sort_unstable();
+        events.dedup();
+        let secret = random_token(32);
+        let row = sqlx::query(
+            "insert into webhooks(doctor_id, url, secret, events) values ($1, $2, $3, $4) returning id",
+        )
+        .bind(owner)
+        .bind(url)
+        .bind(&secret)
+        .bind(&events)
+        .fetch_one(&self.connection)
+        .await?;
+        Ok(CreatedWebhook {
+            id: row.try_get("id")?,
+            secret,
+        })
+    }
+
+    //webhooks of the doctor, or all of them for an admin
+    pub async fn view_webhooks(&self, owner: Option<i64>) -> Result<Vec<Webhook>, AppError> {
+        let query = sqlx::query_as(
+            "select id, url, events, created_at from webhooks
+            where $1::bigint is null or doctor_id = $1 order by id",
+        )
+        .bind(owner);
+        self.get_query_result(query).await
+    }
+
+    pub async fn delete_webhook(
+        &self,
+        webhook_id: i64,
+        owner: Option<i64>,
+    ) -> Result<(), AppError> {
+        let res = sqlx::query(
+            "delete from webhooks where id = $1 and ($2::bigint is null or doctor_id = $2)",
+        )
+        .bind(webhook_id)
+        .bind(owner)
+        .execute(&self.connection)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AppError::NotFound(String::from("No such webhook")));
+        }
+        Ok(())
+    }
+
+    //newest first
+    pub async fn view_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        owner: Option<i64>,
+        page: &CursorQuery,
+    ) -> Result<Page<WebhookDelivery>, AppError> {
+        let cursor = Cursor::<WebhookDelivery>::parse(page)?;
+        sqlx::query(
+            "select 1 from webhooks where id = $1 and ($2::bigint is null or doctor_id = $2)",
+        )
+        .bind(webhook_id)
+        .bind(owner)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("No such webhook")))?;
+        let query = sqlx::query_as(
+            "select id, event, payload, status, attempts, last_status_code, last_error, created_at, delivered_at
+            from webhook_deliveries where webhook_id = $1 and ($2::bigint is null or id < $2)
+            order by id desc limit $3",
+        )
+        .bind(webhook_id)
+        .bind(cursor.after)
+        .bind(cursor.fetch_limit());
+        self.get_page(query, &cursor).await
+    }
+
+    //queues the payload of an earlier delivery again as a new delivery, keeping the old one's history
+    pub async fn replay_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+        owner: Option<i64>,
+    ) -> Result<(), AppError> {
+        let res = sqlx::query(
+            "insert into webhook_deliveries(webhook_id, event, payload)
+            select d.webhook_id, d.event, d.payload from webhook_deliveries d
+            join webhooks w on w.id = d.webhook_id
+            where d.id = $1 and d.webhook_id = $2 and ($3::bigint is null or w.doctor_id = $3)",
+        )
+        .bind(delivery_id)
+        .bind(webhook_id)
+        .bind(owner)
+        .execute(&self.connection)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AppError::NotFound(String::from("No such delivery")));
+        }
+        Ok(())
+    }
+
+    //same lease as the outbox, see claim_outbox
+    pub async fn claim_webhook_deliveries(
+        &self,
+        batch: i64,
+    ) -> Result<Vec<PendingDelivery>, AppError> {
+        let query = sqlx::query_as(
+            "update webhook_deliveries d set next_attempt_at = now() + make_interval(secs => $2)
+            from webhooks w
+            where w.id = d.webhook_id and d.id in (
+                select id from webhook_deliveries where status = 'pending' and next_attempt_at <= now()
+                order by next_attempt_at limit $1 for update skip locked
+            )
+            returning d.id, d.webhook_id, d.event, d.payload, d.attempts, w.url, w.secret",
+        )
+        .bind(batch)
+        .bind(OUTBOX_LEASE_SECS);
+        self.get_query_result(query).await
+    }
+
+    pub async fn webhook_delivered(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "update webhook_deliveries set status = 'delivered', attempts = attempts + 1,
+            delivered_at = now(), last_status_code = $2, last_error = null where id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+
+    //retries at `retry_at`, or gives up on the delivery if there is none
+    pub async fn webhook_failed(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "update webhook_deliveries set attempts = attempts + 1, last_status_code = $2, last_error = $3,
+            status = case when $4::timestamptz is null then 'failed' else 'pending' end,
+            next_attempt_at = coalesce($4, next_attempt_at)
+            where id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(error)
+        .bind(retry_at)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
     }
 
     //tries to find patient/doctor logging in with credentials and gives JWT if successful
-    pub async fn login(&self, email: &String, password: &String) -> Option<String> {
-        let query = format!(
+    pub async fn login(&self, email: &str, password: &str) -> Result<TokenPair, AppError> {
+        let invalid = || AppError::Unauthorized(String::from("Invalid email or password"));
+        let result = sqlx::query_as::<_, LoginTable>(
             "
-                    select salt, password as hashedpass, isdoctor from login where email = '{}';
+                    select salt, password as hashedpass, isdoctor from login where email = $1;
                 ",
-            email
-        );
-        match sqlx::query_as::<_, LoginTable>(&query)
+        )
+        .bind(email)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| {
+            tracing::debug!("No such user found!");
+            invalid()
+        })?;
+        let Ok(check) = argon_hash_password::check_password_matches_hash(
+            password,
+            &result.hashedpass,
+            &result.salt,
+        ) else {
+            tracing::debug!("Couldn't check password matches hash");
+            return Err(invalid());
+        };
+        if !check {
+            return Err(invalid());
+        }
+        //table names can't be bound, so pick between two fixed queries instead
+        let query = if result.isdoctor {
+            "select id from doctors where email = $1;"
+        } else {
+            "select id from patients where email = $1;"
+        };
+        let queryres = sqlx::query(query)
+            .bind(email)
             .fetch_one(&self.connection)
-            .await
-        {
-            Ok(result) => {
-                let Ok(check) = argon_hash_password::check_password_matches_hash(
-                    password,
-                    &result.hashedpass,
-                    &result.salt,
-                ) else {
-                    tracing::debug!("Couldn't check password matches hash");
-                    return None;
-                };
-                if check {
-                    let mut tablename = "patients";
-                    if result.isdoctor {
-                        tablename = "doctors";
-                    }
-                    let query = format!(
-                        "
-                                    select id from {} where email = '{}';
-                                ",
-                        tablename, email
-                    );
-                    let Ok(queryres) = sqlx::query(&query)
-                        .fetch_one(&self.connection)
-                        .await else {
-                            tracing::error!("Error while checking login details in database");
-                            return None;
-                        };
-                    let Ok(id): Result<i64, _> = queryres.try_get("id") else {
-                        tracing::error!("Error while retrieving id from query result");
-                        return None;
-                    };
-                    let jwt = InternalJWT {
-                        isdoctor: result.isdoctor,
-                        id: id.to_string(),
-                        exp: 1000000,
-                    };
-                    let Ok(token) = encode(
-                        &Header::default(),
-                        &jwt,
-                        &EncodingKey::from_secret(&self.jwt_secret),
-                    ) else {
-                        tracing::debug!("Error while trying to encode JWT");
-                        return None;
-                    };
-                    Some(token)
-                } else {
-                    None
-                }
-            }
-            Err(_) => {
-                tracing::debug!("No such user found!");
-                None
-            }
+            .await?;
+        let id: i64 = queryres.try_get("id")?;
+        let mut conn = self.connection.acquire().await?;
+        self.issue_tokens(&mut conn, id, result.isdoctor).await
+    }
+
+    //creates a short lived access JWT and stores a fresh refresh token for the user
+    async fn issue_tokens(
+        &self,
+        conn: &mut PgConnection,
+        id: i64,
+        isdoctor: bool,
+    ) -> Result<TokenPair, AppError> {
+        let now = Utc::now();
+        let jwt = InternalJWT {
+            isdoctor,
+            id: id.to_string(),
+            iat: now.timestamp(),
+            exp: now.timestamp() + self.access_token_ttl,
+            iss: self.jwt_issuer.clone(),
+        };
+        let access_token = encode(
+            &Header::default(),
+            &jwt,
+            &EncodingKey::from_secret(&self.jwt_secret),
+        )
+        .map_err(|e| {
+            tracing::debug!("Error while trying to encode JWT: {}", e);
+            AppError::Internal(String::from("Could not create token"))
+        })?;
+        let refresh_token = random_token(48);
+        sqlx::query(
+            "insert into refresh_tokens(token_hash, user_id, isdoctor, expires_at) values ($1, $2, $3, $4)",
+        )
+        .bind(hash_token(&refresh_token))
+        .bind(id)
+        .bind(isdoctor)
+        .bind(now + ChronoDuration::seconds(self.refresh_token_ttl))
+        .execute(conn)
+        .await?;
+        Ok(TokenPair {
+            access_token,
+            refresh_token,
+            token_type: "Bearer",
+            expires_in: self.access_token_ttl,
+        })
+    }
+
+    //exchanges a refresh token for a new token pair; every refresh token can only be used once
+    pub async fn refresh_tokens(&self, refresh_token: &str) -> Result<TokenPair, AppError> {
+        let invalid = || AppError::Unauthorized(String::from("Invalid refresh token"));
+        let mut tx = self.connection.begin().await?;
+        let row = sqlx::query_as::<_, RefreshTokenRow>(
+            "select id, user_id, isdoctor, expires_at, revoked from refresh_tokens where token_hash = $1 for update",
+        )
+        .bind(hash_token(refresh_token))
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(invalid)?;
+        if row.revoked {
+            //an already rotated token came back, so it may have been stolen; log the user out everywhere
+            tracing::error!(
+                "Refresh token reused, revoking all tokens of user {}",
+                row.user_id
+            );
+            sqlx::query(
+                "update refresh_tokens set revoked = true where user_id = $1 and isdoctor = $2",
+            )
+            .bind(row.user_id)
+            .bind(row.isdoctor)
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await?;
+            return Err(invalid());
+        }
+        if row.expires_at < Utc::now() {
+            return Err(AppError::Unauthorized(String::from(
+                "Refresh token has expired",
+            )));
         }
+        sqlx::query("update refresh_tokens set revoked = true where id = $1")
+            .bind(row.id)
+            .execute(&mut tx)
+            .await?;
+        let pair = self
+            .issue_tokens(&mut tx, row.user_id, row.isdoctor)
+            .await?;
+        tx.commit().await?;
+        Ok(pair)
     }
 
-    pub fn verify_jwt(&self, jwt: &str) -> Option<JWT> {
+    pub fn verify_jwt(&self, jwt: &str) -> Result<Jwt, AppError> {
         let binding = match String::from(jwt)
             .split("Bearer")
             .collect::<Vec<&str>>()
@@ -347,7 +2169,8 @@ impl Database {
             None => jwt.to_string(),
         };
         let mut validation = Validation::default();
-        validation.validate_exp = false;
+        validation.set_issuer(&[&self.jwt_issuer]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss"]);
         let token = binding.trim().to_string();
         tracing::debug!("jwt : '{}'", token);
         match decode::<InternalJWT>(
@@ -356,20 +2179,368 @@ impl Database {
             &validation,
         ) {
             Ok(token) => {
+                //jsonwebtoken checks exp and iss, but not that the token wasn't issued in the future
+                if token.claims.iat > Utc::now().timestamp() + validation.leeway as i64 {
+                    tracing::debug!("JWT issued in the future");
+                    return Err(AppError::Unauthorized(String::from("Invalid token")));
+                }
                 let Ok(id): Result<i64, _> = token.claims.id.parse() else {
                     tracing::error!("Could not parse id while verifiying JWT");
-                    return None;
+                    return Err(AppError::Unauthorized(String::from("Invalid token")));
                 };
-                let res = JWT {
+                Ok(Jwt {
                     isdoctor: token.claims.isdoctor,
                     id,
-                };
-                Some(res)
+                })
             }
             Err(x) => {
                 tracing::debug!("{}", x);
-                None
+                Err(AppError::Unauthorized(String::from("Invalid token")))
+            }
+        }
+    }
+}
+
+//a doctor's working hours and bookings between two days of their zone
+struct Calendar {
+    tz: Tz,
+    from: NaiveDate,
+    to: NaiveDate,
+    hours: Vec<WorkingHours>,
+    booked: Vec<TimeRange>,
+}
+
+//what /find results are sorted by; results are ordered by a key made from the sort value, then
+//by docid and appid. Keys are never null and ascending, so the rows after a cursor are simply
+//those with a greater (key, docid, appid)
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FindSort {
+    Distance,
+    Price,
+    Rating,
+    Soonest,
+}
+
+impl FindSort {
+    fn parse(sort: Option<&str>, has_origin: bool) -> Result<Self, AppError> {
+        match sort {
+            None => Ok(FindSort::Distance),
+            Some("price") => Ok(FindSort::Price),
+            Some("rating") => Ok(FindSort::Rating),
+            Some("soonest") => Ok(FindSort::Soonest),
+            Some("distance") if has_origin => Ok(FindSort::Distance),
+            Some("distance") => Err(AppError::Validation(String::from(
+                "Sorting by distance needs lat and lon",
+            ))),
+            Some(sort) => Err(AppError::Validation(format!(
+                "Can't sort by {}, use price, rating, distance or soonest",
+                sort
+            ))),
+        }
+    }
+
+    //column the sort value is taken from; soonest starts out by distance until the slots are known
+    fn value(self) -> &'static str {
+        match self {
+            FindSort::Distance | FindSort::Soonest => "distance_km",
+            FindSort::Price => "price",
+            FindSort::Rating => "rating",
+        }
+    }
+
+    //goes around a sort value to make its key: unknown distances last, best ratings first
+    fn key(self) -> (&'static str, &'static str) {
+        match self {
+            FindSort::Distance | FindSort::Soonest => ("coalesce(", "::float8, 'infinity')"),
+            FindSort::Price => ("(", "::float8)"),
+            FindSort::Rating => ("-coalesce(", "::float8, 0)"),
+        }
+    }
+}
+
+//the order of soonest sorted /find results: by next slot start, those without a slot last
+fn soonest_order(a: &(Option<f64>, i64, i64), b: &(Option<f64>, i64, i64)) -> Ordering {
+    let start = |key: &(Option<f64>, i64, i64)| key.0.unwrap_or(f64::INFINITY);
+    start(a)
+        .total_cmp(&start(b))
+        .then(a.1.cmp(&b.1))
+        .then(a.2.cmp(&b.2))
+}
+
+//appointments are either physical or virtual
+fn check_mode(mode: &str) -> Result<(), AppError> {
+    if MODES.contains(&mode) {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!(
+            "{} is not a mode, use physical or virtual",
+            mode
+        )))
+    }
+}
+
+//coordinates and radius of a nearest-doctor search, all None if it isn't one
+fn origin_params(origin: Option<Origin>) -> (Option<Coordinates>, Option<f64>) {
+    match origin {
+        Some(origin) => (Some(origin.at), origin.radius_km),
+        None => (None, None),
+    }
+}
+
+fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+async fn record_price_change(
+    conn: &mut PgConnection,
+    doctor_id: i64,
+    apptype: i64,
+    old_price: Option<i32>,
+    new_price: Option<i32>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "insert into appointment_price_history(doctor_id, appointment_type, old_price, new_price) values ($1, $2, $3, $4)",
+    )
+    .bind(doctor_id)
+    .bind(apptype)
+    .bind(old_price)
+    .bind(new_price)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+fn localize_slot((start, end): TimeRange, tz: Tz) -> Slot {
+    Slot {
+        start: zones::localize(&start, tz),
+        end: zones::localize(&end, tz),
+    }
+}
+
+fn check_http_url(url: &str) -> Result<(), AppError> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        _ => Err(AppError::Validation(format!(
+            "{} is not a valid http(s) URL",
+            url
+        ))),
+    }
+}
+
+//trimmed entries of a list shown on a doctor's profile, like their languages
+fn profile_list(field: &str, entries: &[String]) -> Result<Vec<String>, AppError> {
+    if entries.len() > MAX_PROFILE_LIST_LENGTH {
+        return Err(AppError::Validation(format!(
+            "{} can have at most {} entries",
+            field, MAX_PROFILE_LIST_LENGTH
+        )));
+    }
+    entries
+        .iter()
+        .map(|e| match e.trim() {
+            "" => Err(AppError::Validation(format!(
+                "{} can't have empty entries",
+                field
+            ))),
+            e if e.chars().count() > MAX_PROFILE_ENTRY_LENGTH => {
+                Err(AppError::Validation(format!(
+                    "{} entries can be at most {} characters long",
+                    field, MAX_PROFILE_ENTRY_LENGTH
+                )))
             }
+            e => Ok(String::from(e)),
+        })
+        .collect()
+}
+
+fn check_review_length(text: Option<&str>) -> Result<(), AppError> {
+    if text.is_some_and(|t| t.chars().count() > MAX_REVIEW_LENGTH) {
+        return Err(AppError::Validation(format!(
+            "Reviews and replies can be at most {} characters long",
+            MAX_REVIEW_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+//only hashes of refresh tokens are kept, so a leaked table can't be used to log in
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+//an appointment with the names and zones of both parties, callers add the where clause
+const APPOINTMENT_ROW_SELECT: &str =
+    "select a.id, a.doctor_id, a.patient_id, a.appointment_type, a.date_time, a.status,
+    d.name as docname, p.name as patname, d.time_zone as doctor_zone, p.time_zone as patient_zone
+    from only appointments a
+    join doctors d on d.id = a.doctor_id
+    join patients p on p.id = a.patient_id";
+
+async fn fetch_appointment(
+    conn: &mut PgConnection,
+    appointment_id: i64,
+) -> Result<AppointmentRow, AppError> {
+    Ok(
+        sqlx::query_as(&format!("{} where a.id = $1", APPOINTMENT_ROW_SELECT))
+            .bind(appointment_id)
+            .fetch_one(conn)
+            .await?,
+    )
+}
+
+//queues an email or text message, to be sent by the delivery task
+async fn enqueue(
+    conn: &mut PgConnection,
+    channel: Channel,
+    recipient: &str,
+    subject: &str,
+    body: &str,
+) -> Result<(), AppError> {
+    sqlx::query("insert into outbox(channel, recipient, subject, body) values ($1, $2, $3, $4)")
+        .bind(channel.as_str())
+        .bind(recipient)
+        .bind(subject)
+        .bind(body)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+const NOTIFICATION_SUBJECT: &str = "News about your appointment";
+
+//how long a claimed outbox message is left alone before it's tried again
+const OUTBOX_LEASE_SECS: f64 = 300.0;
+
+//keeps slot responses to a reasonable size
+const MAX_SLOT_RANGE_DAYS: i64 = 31;
+
+//events a slow subscriber can fall behind by before it misses some
+const EVENT_BUFFER: usize = 256;
+
+//longest review comment or reply, in characters
+const MAX_REVIEW_LENGTH: usize = 2000;
+
+//limits of what doctors write on their profile, in characters and entries
+const MAX_BIO_LENGTH: usize = 5000;
+const MAX_PROFILE_LIST_LENGTH: usize = 20;
+const MAX_PROFILE_ENTRY_LENGTH: usize = 100;
+
+//most offers /find works out slots for, when filtering by available_on or sorting by soonest
+const MAX_SLOT_CANDIDATES: i64 = 200;
+
+//words of a search query beyond this are ignored
+const MAX_SEARCH_WORDS: usize = 10;
+
+//ways an appointment can take place
+const MODES: [&str; 2] = ["physical", "virtual"];
+
+//moves appointments that ended before the cutoff into patients_previous_appointments; ones that
+//are still scheduled or in progress stay, so the doctor can still finish them
+async fn archive_finished(conn: &mut PgConnection, cutoff: DateTime<Utc>) -> Result<u64, AppError> {
+    let finished: Vec<&str> = AppointmentStatus::FINAL
+        .iter()
+        .map(|status| status.as_str())
+        .collect();
+    let res = sqlx::query(
+        "with moved as (
+            delete from only appointments where ends_at < $1 and status = any($2) returning *
+        )
+        insert into patients_previous_appointments select * from moved",
+    )
+    .bind(cutoff)
+    .bind(finished)
+    .execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+//midnight UTC at the start of the day
+fn day_start(day: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&day.and_time(NaiveTime::MIN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    //needs DATABASE_URL pointing at a migrated database, and is skipped without it; everything
+    //happens in a transaction that is rolled back
+    #[tokio::test]
+    async fn only_finished_appointments_are_archived() {
+        dotenv().ok();
+        let Ok(url) = env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL isn't set, skipping");
+            return;
+        };
+        let mut conn = PgConnection::connect(&url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        let speciality: i64 =
+            sqlx::query_scalar("insert into specialities(name) values ('Archiving') returning id")
+                .fetch_one(&mut tx)
+                .await
+                .unwrap();
+        let apptype: i64 = sqlx::query_scalar(
+            "insert into appointment_types(name, speciality_id) values ('Archiving', $1) returning id",
+        )
+        .bind(speciality)
+        .fetch_one(&mut tx)
+        .await
+        .unwrap();
+        let doctor: i64 = sqlx::query_scalar(
+            "insert into doctors(name, speciality_id, city, address, email, phone)
+            values ('Archiving', $1, 'Nowhere', 'Nowhere', 'archiving@example.com', '0') returning id",
+        )
+        .bind(speciality)
+        .fetch_one(&mut tx)
+        .await
+        .unwrap();
+        let patient: i64 = sqlx::query_scalar(
+            "insert into patients(name, email, phone)
+            values ('Archiving', 'archiving@example.com', '0') returning id",
+        )
+        .fetch_one(&mut tx)
+        .await
+        .unwrap();
+        //one appointment a day in every status, all long over
+        let statuses = [
+            "scheduled",
+            "in_progress",
+            "fulfilled",
+            "no_show",
+            "cancelled",
+        ];
+        for (days_ago, status) in (100..).zip(statuses) {
+            sqlx::query(
+                "insert into appointments(doctor_id, patient_id, appointment_type, date_time, ends_at, type, status)
+                values ($1, $2, $3, now() - make_interval(days => $4), now() - make_interval(days => $4) + interval '30 minutes', 'physical', $5)",
+            )
+            .bind(doctor)
+            .bind(patient)
+            .bind(apptype)
+            .bind(days_ago)
+            .bind(status)
+            .execute(&mut tx)
+            .await
+            .unwrap();
         }
+        let archived = archive_finished(&mut tx, Utc::now() - ChronoDuration::days(30))
+            .await
+            .map_err(|_| "archiving failed")
+            .unwrap();
+        assert!(archived >= 3);
+        let left: Vec<String> = sqlx::query_scalar(
+            "select status from only appointments where doctor_id = $1 order by status",
+        )
+        .bind(doctor)
+        .fetch_all(&mut tx)
+        .await
+        .unwrap();
+        assert_eq!(left, ["in_progress", "scheduled"]);
+        tx.rollback().await.unwrap();
     }
 }
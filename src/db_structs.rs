@@ -1,9 +1,12 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, Utc};
 use serde::de::{self, Deserializer};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::fmt::Display;
 use std::str::FromStr;
 
+use crate::pagination::Keyset;
+
 //inputs; input JSON -> serde -> these structs
 #[derive(Deserialize)]
 pub struct Login {
@@ -12,9 +15,114 @@ pub struct Login {
 }
 
 #[derive(Deserialize)]
-pub struct PatientID {
+pub struct RescheduleAppointment {
     #[serde(deserialize_with = "from_str")]
-    pub patient_id: i64,
+    pub appointment_id: i64,
+    pub new_datetime: DateTime<FixedOffset>,
+}
+
+//rating is 1 to 5, the appointment has to be one of the patient's fulfilled ones
+#[derive(Deserialize)]
+pub struct NewReview {
+    #[serde(deserialize_with = "from_str")]
+    pub appointment_id: i64,
+    pub rating: i16,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewReply {
+    pub reply: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewModeration {
+    pub hidden: bool,
+    pub reason: Option<String>,
+}
+
+//hidden picks only hidden or only visible reviews, both if not given
+#[derive(Deserialize)]
+pub struct ModerationQuery {
+    pub hidden: Option<bool>,
+}
+
+//offers the appointment type at this price, or changes the price if it is already offered
+#[derive(Deserialize)]
+pub struct PriceUpdate {
+    #[serde(deserialize_with = "from_str")]
+    pub apptype: i64,
+    pub price: i32,
+    //physical and/or virtual, unchanged if left out; new offers can be booked either way
+    pub modes: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct AppointmentStatusUpdate {
+    #[serde(deserialize_with = "from_str")]
+    pub appointment_id: i64,
+    pub status: String,
+    pub prescription: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Schedule {
+    pub hours: Vec<WorkingHours>,
+}
+
+//from and to are dates in the doctor's time zone; tz optionally picks the zone the slots are shown in
+#[derive(Deserialize)]
+pub struct SlotQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub apptype: i64,
+    pub tz: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NotificationQuery {
+    #[serde(default)]
+    pub unread: bool,
+}
+
+//q is free text like "skin doctor chicago", the rest narrow the results down
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub city: Option<String>,
+    pub speciality: Option<i64>,
+    pub apptype: Option<i64>,
+}
+
+//cursor is the next_cursor of the previous page, left out for the first page
+#[derive(Deserialize)]
+pub struct CursorQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+//next_cursor is None on the last page
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+//events must be a subset of webhooks::EVENTS
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    pub access_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshToken {
+    pub refresh_token: String,
 }
 
 #[derive(Deserialize)]
@@ -23,6 +131,8 @@ pub struct Patient {
     pub email: String,
     pub phone: String,
     pub password: String,
+    //IANA time zone name, UTC if not given
+    pub time_zone: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -35,17 +145,62 @@ pub struct Doctor {
     pub email: String,
     pub phone: String,
     pub password: String,
+    //IANA time zone name, UTC if not given
+    pub time_zone: Option<String>,
+    //looked up from the address if not given
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+//only the fields given are changed; an empty bio or photo_url removes it
+#[derive(Deserialize)]
+pub struct ProfileUpdate {
+    pub name: Option<String>,
+    pub city: Option<String>,
+    pub address: Option<String>,
+    pub phone: Option<String>,
+    pub time_zone: Option<String>,
+    pub bio: Option<String>,
+    pub qualifications: Option<Vec<String>>,
+    pub years_of_experience: Option<i32>,
+    pub languages: Option<Vec<String>>,
+    pub photo_url: Option<String>,
+    //looked up again from the new address if it changes and these aren't given
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
 }
 
+//an empty city means any city; lat and lon sort by distance from there, radius_km in km around it
 #[derive(Deserialize)]
 pub struct City {
+    #[serde(default)]
     pub city: String,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub radius_km: Option<f64>,
 }
 
+//like City, an empty apptype means any appointment type
+//every filter is optional; languages is comma separated and all of them have to be spoken,
+//available_on is a date in the doctor's time zone
 #[derive(Deserialize)]
-pub struct CityApptype {
+pub struct FindQuery {
+    #[serde(default)]
     pub city: String,
+    #[serde(default)]
     pub apptype: String,
+    pub speciality: Option<i64>,
+    pub min_price: Option<i32>,
+    pub max_price: Option<i32>,
+    pub mode: Option<String>,
+    pub languages: Option<String>,
+    pub min_rating: Option<f64>,
+    pub available_on: Option<NaiveDate>,
+    //price, rating, distance or soonest; by distance (if known) when left out
+    pub sort: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub radius_km: Option<f64>,
 }
 
 #[derive(Deserialize)]
@@ -53,46 +208,37 @@ pub struct Appointment {
     #[serde(deserialize_with = "from_str")]
     pub doctor_id: i64,
     #[serde(deserialize_with = "from_str")]
-    pub patient_id: i64,
-    #[serde(deserialize_with = "from_str")]
     pub apptype: i64,
-    pub datetime: String,
+    pub datetime: DateTime<FixedOffset>,
     pub phyorvirt: String,
-    pub status: String,
-    pub prescription: String,
 }
 
 #[derive(Deserialize)]
 pub struct CancelAppointment {
     #[serde(deserialize_with = "from_str")]
     pub doctor_id: i64,
-    #[serde(deserialize_with = "from_str")]
-    pub patient_id: i64,
-    pub datetime: String,
-}
-
-#[derive(Deserialize)]
-pub struct Registration {
-    pub email: String,
-    pub password: String,
-    pub isdoctor: bool,
+    pub datetime: DateTime<FixedOffset>,
 }
 
 //outputs; SQL query -> sqlx -> these structs -> serde -> output JSON
+//times are RFC 3339 in the zone of whoever asked for them
 #[derive(FromRow, Serialize)]
 pub struct Prescriptions {
+    #[serde(skip)]
+    id: i64,
     docname: String,
-    timestamp: String,
+    pub timestamp: DateTime<FixedOffset>,
     prescription: String,
 }
 
 #[derive(FromRow, Serialize)]
 pub struct PrevAppointments {
+    id: i64,
     docname: String,
-    timestamp: String,
+    pub timestamp: DateTime<FixedOffset>,
     apptype: String,
     appstatus: String,
-    prescription: String,
+    prescription: Option<String>,
     appname: String,
 }
 
@@ -102,6 +248,74 @@ pub struct DoctorInfo {
     docname: String,
     specname: String,
     address: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    //only with a lat and lon in the request and coordinates for the doctor
+    distance_km: Option<f64>,
+    //average of the visible reviews, None without any
+    rating: Option<f64>,
+    review_count: i64,
+}
+
+//fuzzy results only matched by similar spelling, they are only given if nothing matched exactly
+#[derive(FromRow, Serialize)]
+pub struct DoctorSearchResult {
+    docid: i64,
+    docname: String,
+    specname: String,
+    city: String,
+    address: String,
+    rank: f32,
+    fuzzy: bool,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct DoctorProfileRow {
+    id: i64,
+    name: String,
+    speciality_id: i32,
+    speciality: String,
+    city: String,
+    address: String,
+    phone: String,
+    pub time_zone: String,
+    bio: Option<String>,
+    qualifications: Vec<String>,
+    years_of_experience: Option<i32>,
+    languages: Vec<String>,
+    photo_url: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    //average of the visible reviews, None without any
+    rating: Option<f64>,
+    review_count: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct AppointmentPrice {
+    appid: i64,
+    name: String,
+    description: Option<String>,
+    pub duration_minutes: i32,
+    price: i32,
+    modes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct OfferedAppointment {
+    #[serde(flatten)]
+    pub apptype: AppointmentPrice,
+    pub next_available: Option<Slot>,
+}
+
+//slots are looked for in the next MAX_SLOT_RANGE_DAYS days and shown in the doctor's zone
+#[derive(Serialize)]
+pub struct DoctorProfile {
+    #[serde(flatten)]
+    pub info: DoctorProfileRow,
+    pub appointment_types: Vec<OfferedAppointment>,
+    //earliest of the appointment types' next slots
+    pub next_available: Option<Slot>,
 }
 
 #[derive(FromRow, Serialize)]
@@ -113,27 +327,155 @@ pub struct PatientInfo {
 
 #[derive(FromRow, Serialize)]
 pub struct DoctorPrices {
-    docid: i64,
+    pub docid: i64,
     docname: String,
     city: String,
     address: String,
     apptype: String,
     appid: i64,
     price: i32,
+    modes: Vec<String>,
+    //what the results are sorted by, see database::FindSort
+    #[serde(skip)]
+    pub sort_value: Option<f64>,
+    #[serde(skip)]
+    pub duration_minutes: i32,
+    #[serde(skip)]
+    pub time_zone: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    //only with a lat and lon in the request and coordinates for the doctor
+    distance_km: Option<f64>,
+    //average of the visible reviews, None without any
+    rating: Option<f64>,
+    review_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct FoundDoctor {
+    #[serde(flatten)]
+    pub doctor: DoctorPrices,
+    //only worked out when filtering by available_on or sorting by soonest, null if there's no free
+    //slot that day or in the next MAX_SLOT_RANGE_DAYS days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_available: Option<Option<Slot>>,
+}
+
+impl Keyset for Prescriptions {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.timestamp.with_timezone(&Utc), self.id)
+    }
+}
+
+impl Keyset for PrevAppointments {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.timestamp.with_timezone(&Utc), self.id)
+    }
+}
+
+impl Keyset for DoctorAppointments {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.datetime.with_timezone(&Utc), self.id)
+    }
+}
+
+impl Keyset for DoctorInfo {
+    type Key = (Option<f64>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.distance_km, self.docid)
+    }
+}
+
+impl Keyset for DoctorPrices {
+    type Key = (Option<f64>, i64, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.sort_value, self.docid, self.appid)
+    }
+}
+
+impl Keyset for FoundDoctor {
+    type Key = (Option<f64>, i64, i64);
+
+    fn key(&self) -> Self::Key {
+        self.doctor.key()
+    }
+}
+
+impl Keyset for DoctorSearchResult {
+    type Key = (f32, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.rank, self.docid)
+    }
+}
+
+impl Keyset for Notification {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.datetime.with_timezone(&Utc), self.id)
+    }
+}
+
+impl Keyset for Review {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.created_at, self.id)
+    }
+}
+
+impl Keyset for ModeratedReview {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.created_at, self.id)
+    }
+}
+
+impl Keyset for PriceChange {
+    type Key = (DateTime<Utc>, i64);
+
+    fn key(&self) -> Self::Key {
+        (self.changed_at, self.id)
+    }
+}
+
+impl Keyset for WebhookDelivery {
+    type Key = i64;
+
+    fn key(&self) -> Self::Key {
+        self.id
+    }
+}
+
+impl Keyset for Cities {
+    type Key = String;
+
+    fn key(&self) -> Self::Key {
+        self.city.clone()
+    }
 }
 
 #[derive(FromRow, Serialize)]
 pub struct Apptypes {
     id: i64,
-    name: String
+    name: String,
 }
 
 #[derive(FromRow, Serialize)]
 pub struct Cities {
-    city: String
+    city: String,
 }
 
-
 #[derive(FromRow, Serialize)]
 pub struct DoctorAppointments {
     #[serde(deserialize_with = "from_str")]
@@ -142,10 +484,32 @@ pub struct DoctorAppointments {
     patient_id: i32,
     #[serde(deserialize_with = "from_str")]
     apptype: i32,
-    pub datetime: String,
+    pub datetime: DateTime<FixedOffset>,
     phyorvirt: String,
     pub status: String,
-    prescription: String,
+    prescription: Option<String>,
+}
+
+//weekday is 1 (Monday) to 7 (Sunday); also used as input when a doctor sets their hours
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct WorkingHours {
+    pub weekday: i16,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Slot {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Notification {
+    id: i64,
+    message: String,
+    pub datetime: DateTime<FixedOffset>,
+    read: bool,
 }
 
 #[derive(FromRow, Serialize)]
@@ -155,6 +519,109 @@ pub struct Specialities {
     desc: String,
 }
 
+//an appointment together with the names and time zones of both parties
+#[derive(FromRow)]
+pub struct AppointmentRow {
+    pub id: i64,
+    pub doctor_id: i32,
+    pub patient_id: i32,
+    pub appointment_type: i32,
+    pub date_time: DateTime<Utc>,
+    pub status: String,
+    pub docname: String,
+    pub patname: String,
+    pub doctor_zone: String,
+    pub patient_zone: String,
+}
+
+//an email or text message waiting in the outbox
+#[derive(FromRow)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub channel: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+}
+
+//the secret is only ever shown here, when the webhook is created
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+    pub id: i64,
+    pub secret: String,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Webhook {
+    id: i64,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+//a review as shown to everyone, without who wrote it
+#[derive(FromRow, Serialize)]
+pub struct Review {
+    id: i64,
+    rating: i16,
+    comment: Option<String>,
+    reply: Option<String>,
+    created_at: DateTime<Utc>,
+    replied_at: Option<DateTime<Utc>>,
+}
+
+//a review as shown to admins
+#[derive(FromRow, Serialize)]
+pub struct ModeratedReview {
+    id: i64,
+    appointment_id: i64,
+    doctor_id: i32,
+    patient_id: i32,
+    rating: i16,
+    comment: Option<String>,
+    reply: Option<String>,
+    hidden: bool,
+    hidden_reason: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct PriceChange {
+    #[serde(skip)]
+    id: i64,
+    appid: i64,
+    apptype: String,
+    old_price: Option<i32>,
+    new_price: Option<i32>,
+    changed_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct WebhookDelivery {
+    id: i64,
+    event: String,
+    payload: String,
+    status: String,
+    attempts: i32,
+    last_status_code: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+//a webhook delivery claimed for sending, with what is needed to send it
+#[derive(FromRow)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
 #[derive(FromRow, Serialize)]
 pub struct LoginTable {
     pub salt: String,
@@ -163,7 +630,7 @@ pub struct LoginTable {
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct JWT {
+pub struct Jwt {
     pub isdoctor: bool,
     #[serde(deserialize_with = "from_str")]
     pub id: i64,
@@ -173,7 +640,34 @@ pub struct JWT {
 pub struct InternalJWT {
     pub isdoctor: bool,
     pub id: String,
-    pub exp: usize,
+    pub iat: i64,
+    pub exp: i64,
+    pub iss: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    //seconds until the access token expires
+    pub expires_in: i64,
+}
+
+#[derive(FromRow)]
+pub struct DoctorAddress {
+    pub id: i64,
+    pub address: String,
+    pub city: String,
+}
+
+#[derive(FromRow)]
+pub struct RefreshTokenRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub isdoctor: bool,
+    pub expires_at: DateTime<Utc>,
+    pub revoked: bool,
 }
 
 //function to convert the input string into a number with some Serde magic
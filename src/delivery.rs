@@ -0,0 +1,285 @@
+//delivers emails and text messages from the outbox table through pluggable transports
+use axum::async_trait;
+use chrono::{Duration as ChronoDuration, Utc};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use serde::Serialize;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::database::{env_or, Database};
+use crate::db_structs::OutboxMessage;
+
+pub type DeliveryError = Box<dyn Error + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    Email,
+    Sms,
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Email => "email",
+            Channel::Sms => "sms",
+        }
+    }
+
+    //name of the setting that picks the channel's transport
+    fn setting(self) -> &'static str {
+        match self {
+            Channel::Email => "EMAIL_TRANSPORT",
+            Channel::Sms => "SMS_TRANSPORT",
+        }
+    }
+
+    //emails go to the log unless configured otherwise, text messages aren't sent at all
+    fn transport_kind(self) -> String {
+        let default = match self {
+            Channel::Email => "log",
+            Channel::Sms => "none",
+        };
+        env_or(self.setting(), String::from(default))
+    }
+}
+
+//channels that have a transport; messages are only queued for these
+pub fn enabled_channels() -> Vec<Channel> {
+    [Channel::Email, Channel::Sms]
+        .into_iter()
+        .filter(|c| c.transport_kind() != "none")
+        .collect()
+}
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, message: &OutboxMessage) -> Result<(), DeliveryError>;
+}
+
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpTransport {
+    //SMTP_TLS is starttls (default), tls or none; none is meant for local mock servers
+    fn from_env() -> Result<Self, DeliveryError> {
+        let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST is not set")?;
+        let builder = match env_or("SMTP_TLS", String::from("starttls")).as_str() {
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)?,
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)?,
+        };
+        let mut builder = builder.port(env_or("SMTP_PORT", 587));
+        if let (Ok(user), Ok(pass)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(user, pass));
+        }
+        Ok(SmtpTransport {
+            mailer: builder.build(),
+            from: env_or("EMAIL_FROM", String::from("noreply@localhost")).parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for SmtpTransport {
+    async fn send(&self, message: &OutboxMessage) -> Result<(), DeliveryError> {
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(message.recipient.parse()?)
+            .subject(&message.subject)
+            .body(message.body.clone())?;
+        self.mailer.send(email).await?;
+        Ok(())
+    }
+}
+
+//posts {"from", "to", "body"} as JSON to the gateway, any 2xx response counts as sent
+pub struct HttpSmsTransport {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    from: String,
+}
+
+#[derive(Serialize)]
+struct SmsRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    body: &'a str,
+}
+
+impl HttpSmsTransport {
+    fn from_env() -> Result<Self, DeliveryError> {
+        Ok(HttpSmsTransport {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            url: env::var("SMS_GATEWAY_URL").map_err(|_| "SMS_GATEWAY_URL is not set")?,
+            token: env::var("SMS_GATEWAY_TOKEN").ok(),
+            from: env_or("SMS_FROM", String::from("Excalibur")),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for HttpSmsTransport {
+    async fn send(&self, message: &OutboxMessage) -> Result<(), DeliveryError> {
+        let mut request = self.client.post(&self.url).json(&SmsRequest {
+            from: &self.from,
+            to: &message.recipient,
+            body: &message.body,
+        });
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+//appends every message to a file, handy for tests and local development
+pub struct FileTransport {
+    path: PathBuf,
+}
+
+#[async_trait]
+impl Transport for FileTransport {
+    async fn send(&self, message: &OutboxMessage) -> Result<(), DeliveryError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let entry = format!(
+            "{} {} to {}: {}\n{}\n\n",
+            Utc::now().to_rfc3339(),
+            message.channel,
+            message.recipient,
+            message.subject,
+            message.body
+        );
+        file.write_all(entry.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+//only logs the message
+pub struct LogTransport;
+
+#[async_trait]
+impl Transport for LogTransport {
+    async fn send(&self, message: &OutboxMessage) -> Result<(), DeliveryError> {
+        tracing::info!(
+            "Delivering {} to {}: {} - {}",
+            message.channel,
+            message.recipient,
+            message.subject,
+            message.body
+        );
+        Ok(())
+    }
+}
+
+//the transport of every enabled channel
+pub struct Transports {
+    email: Option<Box<dyn Transport>>,
+    sms: Option<Box<dyn Transport>>,
+}
+
+impl Transports {
+    //built from EMAIL_TRANSPORT and SMS_TRANSPORT, each one of smtp/http, file, log or none
+    pub fn from_env() -> Result<Self, DeliveryError> {
+        Ok(Transports {
+            email: transport(Channel::Email)?,
+            sms: transport(Channel::Sms)?,
+        })
+    }
+
+    fn get(&self, channel: &str) -> Option<&dyn Transport> {
+        match channel {
+            "email" => self.email.as_deref(),
+            "sms" => self.sms.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+fn transport(channel: Channel) -> Result<Option<Box<dyn Transport>>, DeliveryError> {
+    let transport: Box<dyn Transport> = match (channel, channel.transport_kind().as_str()) {
+        (_, "none") => return Ok(None),
+        (_, "log") => Box::new(LogTransport),
+        (_, "file") => Box::new(FileTransport {
+            path: env_or("OUTBOX_FILE", PathBuf::from("outbox.log")),
+        }),
+        (Channel::Email, "smtp") => Box::new(SmtpTransport::from_env()?),
+        (Channel::Sms, "http") => Box::new(HttpSmsTransport::from_env()?),
+        (_, kind) => {
+            return Err(format!("Unknown {} transport {}", channel.as_str(), kind).into());
+        }
+    };
+    Ok(Some(transport))
+}
+
+//waits this long before the nth retry, doubling every time up to an hour
+pub fn backoff(attempts: i32) -> ChronoDuration {
+    let secs = 30i64.saturating_mul(1 << attempts.clamp(1, 20).saturating_sub(1));
+    ChronoDuration::seconds(secs.min(60 * 60))
+}
+
+pub fn spawn(conn: Arc<Database>, transports: Transports) {
+    let every_secs: u64 = env_or("OUTBOX_INTERVAL_SECS", 5);
+    let batch: i64 = env_or("OUTBOX_BATCH_SIZE", 20);
+    let max_attempts: i32 = env_or("OUTBOX_MAX_ATTEMPTS", 8);
+    tracing::debug!(
+        "Delivering up to {} outbox messages every {}s, giving up after {} attempts",
+        batch,
+        every_secs,
+        max_attempts
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(every_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let messages = match conn.claim_outbox(batch).await {
+                Ok(messages) => messages,
+                Err(_) => {
+                    tracing::error!("Reading the outbox failed, retrying next time");
+                    continue;
+                }
+            };
+            for message in messages {
+                let res = match transports.get(&message.channel) {
+                    Some(transport) => transport.send(&message).await,
+                    None => Err(format!("No transport for {}", message.channel).into()),
+                };
+                let attempts = message.attempts + 1;
+                let saved = match res {
+                    Ok(()) => conn.outbox_sent(message.id).await,
+                    Err(e) => {
+                        tracing::error!(
+                            "Delivering outbox message {} failed (attempt {}): {}",
+                            message.id,
+                            attempts,
+                            e
+                        );
+                        let retry_at =
+                            (attempts < max_attempts).then(|| Utc::now() + backoff(attempts));
+                        conn.outbox_failed(message.id, &e.to_string(), retry_at)
+                            .await
+                    }
+                };
+                if saved.is_err() {
+                    tracing::error!("Could not save outcome of outbox message {}", message.id);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,160 @@
+//single error type shared by the database layer and the handlers
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+pub enum AppError {
+    //input failed our own checks before reaching the database
+    Validation(String),
+    //no or bad token
+    Unauthorized(String),
+    //valid token, but not allowed to touch this resource
+    Forbidden(String),
+    NotFound(String),
+    //record clashes with an existing one (duplicate email, booked slot, ...)
+    Conflict {
+        message: String,
+        details: Option<String>,
+    },
+    //insert/update points at a row that doesn't exist (foreign key)
+    InvalidReference {
+        message: String,
+        details: Option<String>,
+    },
+    //value rejected by a check constraint like chk_status
+    ConstraintViolation {
+        message: String,
+        details: Option<String>,
+    },
+    //couldn't get a connection or the database went away
+    Unavailable(String),
+    Internal(String),
+}
+
+//body of every error response
+#[derive(Serialize)]
+pub struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Option<String>,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::InvalidReference { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ConstraintViolation { .. } => StatusCode::BAD_REQUEST,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn body(self) -> ErrorBody {
+        let (code, message, details) = match self {
+            AppError::Validation(m) => ("validation_failed", m, None),
+            AppError::Unauthorized(m) => ("unauthorized", m, None),
+            AppError::Forbidden(m) => ("forbidden", m, None),
+            AppError::NotFound(m) => ("not_found", m, None),
+            AppError::Conflict { message, details } => ("conflict", message, details),
+            AppError::InvalidReference { message, details } => {
+                ("invalid_reference", message, details)
+            }
+            AppError::ConstraintViolation { message, details } => {
+                ("constraint_violation", message, details)
+            }
+            AppError::Unavailable(m) => ("database_unavailable", m, None),
+            AppError::Internal(m) => ("internal_error", m, None),
+        };
+        ErrorBody {
+            code,
+            message,
+            details,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let code = self.status();
+        (code, Json(self.body())).into_response()
+    }
+}
+
+//postgres error codes, see https://www.postgresql.org/docs/current/errcodes-appendix.html
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => AppError::NotFound(String::from("No such record")),
+            sqlx::Error::Database(dberr) => {
+                let details = dberr.constraint().map(String::from);
+                match dberr.code().as_deref() {
+                    Some("23505") => AppError::Conflict {
+                        message: String::from("Record already exists"),
+                        details,
+                    },
+                    Some("23P01") => AppError::Conflict {
+                        message: String::from("Record overlaps an existing one"),
+                        details,
+                    },
+                    Some("23503") => AppError::InvalidReference {
+                        message: String::from("Referenced record does not exist"),
+                        details,
+                    },
+                    Some("23514") | Some("23502") | Some("22P02") => {
+                        AppError::ConstraintViolation {
+                            message: String::from("Invalid value given"),
+                            details,
+                        }
+                    }
+                    _ => {
+                        tracing::error!("Database error: {}", dberr);
+                        AppError::Internal(String::from("Database error"))
+                    }
+                }
+            }
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                tracing::error!("Database unavailable: {}", e);
+                AppError::Unavailable(String::from("Database is unavailable"))
+            }
+            _ => {
+                tracing::error!("Error while running query: {}", e);
+                AppError::Internal(String::from("Database error"))
+            }
+        }
+    }
+}
+
+//requests axum couldn't read into what the handler takes, see extract.rs
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection {
+            PathRejection::FailedToDeserializePathParams(e) => AppError::Validation(e.body_text()),
+            //a route whose handler takes more path parameters than it has
+            e => {
+                tracing::error!("Could not extract path parameters: {}", e.body_text());
+                AppError::Internal(String::from("Could not read the path"))
+            }
+        }
+    }
+}
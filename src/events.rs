@@ -0,0 +1,121 @@
+//pushes appointment changes to the affected doctor and patient as server sent events
+//changes are published with pg_notify inside the transaction making them, so they only go out
+//once committed and reach the clients of every server instance
+use axum::response::sse::Event;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgConnection, PgListener};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::auth::{AuthUser, Role};
+use crate::database::Database;
+use crate::db_structs::AppointmentRow;
+use crate::error::AppError;
+use crate::webhooks;
+
+//postgres channel the events are sent on
+const CHANNEL: &str = "appointment_events";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AppointmentEvent {
+    //one of webhooks::EVENTS
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub appointment_id: i64,
+    pub doctor_id: i32,
+    pub patient_id: i32,
+    pub date_time: DateTime<Utc>,
+    pub status: String,
+}
+
+impl AppointmentEvent {
+    pub fn new(kind: &str, app: &AppointmentRow) -> Self {
+        AppointmentEvent {
+            kind: String::from(kind),
+            appointment_id: app.id,
+            doctor_id: app.doctor_id,
+            patient_id: app.patient_id,
+            date_time: app.date_time,
+            status: app.status.clone(),
+        }
+    }
+
+    fn concerns(&self, user: &AuthUser) -> bool {
+        match user.role {
+            Role::Doctor => i64::from(self.doctor_id) == user.id,
+            Role::Patient => i64::from(self.patient_id) == user.id,
+        }
+    }
+}
+
+//sends the event to connected clients and queues it for webhooks
+pub async fn publish(conn: &mut PgConnection, event: &AppointmentEvent) -> Result<(), AppError> {
+    let payload = serde_json::to_string(event)
+        .map_err(|_| AppError::Internal(String::from("Could not encode event")))?;
+    sqlx::query("select pg_notify($1, $2)")
+        .bind(CHANNEL)
+        .bind(&payload)
+        .execute(&mut *conn)
+        .await?;
+    webhooks::queue(conn, event, &payload).await
+}
+
+//listens for events of all server instances and hands them to this instance's subscribers
+pub fn spawn(conn: Arc<Database>) {
+    tokio::spawn(async move {
+        loop {
+            let mut listener = match PgListener::connect_with(conn.pool()).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!("Could not connect event listener: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            if let Err(e) = listener.listen(CHANNEL).await {
+                tracing::error!("Could not listen for events: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+            tracing::debug!("Listening for appointment events");
+            //recv reconnects by itself if the connection drops, events sent meanwhile are lost
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        match serde_json::from_str::<AppointmentEvent>(notification.payload()) {
+                            //an error only means nobody is subscribed right now
+                            Ok(event) => {
+                                let _ = conn.events().send(event);
+                            }
+                            Err(e) => tracing::error!("Ignoring malformed event: {}", e),
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Event listener failed: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+//events concerning the user, from now on
+pub fn stream(conn: &Database, user: AuthUser) -> impl Stream<Item = Result<Event, Infallible>> {
+    BroadcastStream::new(conn.events().subscribe()).filter_map(move |event| match event {
+        Ok(event) if event.concerns(&user) => Event::default()
+            .event(event.kind.clone())
+            .json_data(&event)
+            .ok()
+            .map(Ok),
+        Ok(_) => None,
+        Err(e) => {
+            //a client too slow to keep up misses events instead of holding everyone else up
+            tracing::error!("Event subscriber of user {} lagging: {}", user.id, e);
+            None
+        }
+    })
+}
@@ -0,0 +1,89 @@
+//axum's Json, Query and Path extractors, but rejecting with AppError, so a malformed body, query
+//string or path gets the same JSON error body as every other error
+use axum::extract::{FromRequest, FromRequestParts};
+
+use crate::error::AppError;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct AppJson<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct AppQuery<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct AppPath<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::post,
+        Json, Router,
+    };
+    use serde::Deserialize;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize)]
+    struct Payload {
+        id: i64,
+    }
+
+    async fn handler(
+        AppPath(id): AppPath<i64>,
+        AppQuery(query): AppQuery<Payload>,
+        AppJson(payload): AppJson<Payload>,
+    ) -> Json<i64> {
+        Json(id + query.id + payload.id)
+    }
+
+    //status and JSON body of a request to /items/{path}?{query} with the given body
+    async fn send(path: &str, query: &str, body: &'static str) -> (StatusCode, Value) {
+        let app = Router::new().route("/items/:id", post(handler));
+        let request = Request::post(format!("/items/{}?{}", path, query))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn invalid_json_gets_a_json_error() {
+        let (status, body) = send("1", "id=1", "{\"id\": ").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation_failed");
+        assert!(body["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn json_of_the_wrong_shape_gets_a_json_error() {
+        let (status, body) = send("1", "id=1", "{\"id\": \"one\"}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation_failed");
+    }
+
+    #[tokio::test]
+    async fn bad_query_and_path_get_a_json_error() {
+        let (status, body) = send("1", "id=one", "{\"id\": 1}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation_failed");
+        let (status, body) = send("one", "id=1", "{\"id\": 1}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation_failed");
+    }
+
+    #[tokio::test]
+    async fn valid_requests_reach_the_handler() {
+        let (status, body) = send("1", "id=2", "{\"id\": 3}").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, 6);
+    }
+}
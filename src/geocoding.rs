@@ -0,0 +1,259 @@
+//turns doctor addresses into coordinates through a pluggable geocoder, and checks the
+//coordinates nearest-doctor searches are made from
+use axum::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::database::env_or;
+use crate::error::AppError;
+
+pub type GeocodeError = Box<dyn Error + Send + Sync>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self, AppError> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(AppError::Validation(String::from(
+                "Latitude must be between -90 and 90 and longitude between -180 and 180",
+            )));
+        }
+        Ok(Coordinates {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+//where a nearest-doctor search is made from; without a radius every doctor is kept, sorted by distance
+pub struct Origin {
+    pub at: Coordinates,
+    pub radius_km: Option<f64>,
+}
+
+//lat and lon have to be given together, radius_km only together with them
+pub fn origin(
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius_km: Option<f64>,
+) -> Result<Option<Origin>, AppError> {
+    match (lat, lon, radius_km) {
+        (None, None, None) => Ok(None),
+        (Some(lat), Some(lon), radius_km) => {
+            if radius_km.is_some_and(|r| r.is_nan() || r <= 0.0) {
+                return Err(AppError::Validation(String::from(
+                    "radius_km must be positive",
+                )));
+            }
+            Ok(Some(Origin {
+                at: Coordinates::new(lat, lon)?,
+                radius_km,
+            }))
+        }
+        _ => Err(AppError::Validation(String::from(
+            "lat and lon have to be given together, and radius_km only with them",
+        ))),
+    }
+}
+
+#[async_trait]
+pub trait Geocoder: Send + Sync {
+    //None if the address isn't known
+    async fn locate(&self, address: &str, city: &str) -> Result<Option<Coordinates>, GeocodeError>;
+}
+
+//never finds anything, doctors only get coordinates if they give them
+pub struct NoGeocoder;
+
+#[async_trait]
+impl Geocoder for NoGeocoder {
+    async fn locate(&self, _: &str, _: &str) -> Result<Option<Coordinates>, GeocodeError> {
+        Ok(None)
+    }
+}
+
+//looks addresses up in a CSV file with the columns city,address,latitude,longitude and a header
+//line; a row with an empty address is used for any address in that city that isn't listed
+pub struct CsvGeocoder {
+    places: HashMap<(String, String), Coordinates>,
+}
+
+impl CsvGeocoder {
+    pub fn parse(contents: &str) -> Result<Self, GeocodeError> {
+        let mut places = HashMap::new();
+        for (i, line) in contents.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = split_fields(line)
+                .ok_or_else(|| format!("Line {} has an unclosed quote", i + 1))?;
+            if fields.len() < 4 {
+                return Err(format!("Line {} doesn't have 4 columns", i + 1).into());
+            }
+            let (Ok(lon), Ok(lat)) = (
+                fields.pop().unwrap_or_default().trim().parse(),
+                fields.pop().unwrap_or_default().trim().parse(),
+            ) else {
+                return Err(format!("Line {} has coordinates that aren't numbers", i + 1).into());
+            };
+            let coordinates = Coordinates::new(lat, lon)
+                .map_err(|_| format!("Line {} has coordinates out of range", i + 1))?;
+            //addresses may contain unquoted commas too, the coordinates never do
+            let address = fields[1..].join(",");
+            places.insert((normalize(&fields[0]), normalize(&address)), coordinates);
+        }
+        Ok(CsvGeocoder { places })
+    }
+}
+
+#[async_trait]
+impl Geocoder for CsvGeocoder {
+    async fn locate(&self, address: &str, city: &str) -> Result<Option<Coordinates>, GeocodeError> {
+        let city = normalize(city);
+        Ok(self
+            .places
+            .get(&(city.clone(), normalize(address)))
+            .or_else(|| self.places.get(&(city, String::new())))
+            .copied())
+    }
+}
+
+//fields of a CSV line; quoted fields may contain commas, with "" standing for a quote. None if
+//a quote isn't closed
+fn split_fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut()?;
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    (!quoted).then_some(fields)
+}
+
+//lower case with single spaces, so "123  Main St" and "123 main st" are the same place
+fn normalize(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+//GEOCODER is csv (reading GEOCODER_FILE) or none (default)
+pub fn from_env() -> Result<Box<dyn Geocoder>, GeocodeError> {
+    match env_or("GEOCODER", String::from("none")).as_str() {
+        "none" => Ok(Box::new(NoGeocoder)),
+        "csv" => {
+            let path = env_or("GEOCODER_FILE", PathBuf::from("geocoder.csv"));
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            Ok(Box::new(CsvGeocoder::parse(&contents)?))
+        }
+        kind => Err(format!("Unknown geocoder {}", kind).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "city,address,latitude,longitude\n";
+
+    fn csv(rows: &str) -> Result<CsvGeocoder, GeocodeError> {
+        CsvGeocoder::parse(&format!("{}{}", HEADER, rows))
+    }
+
+    async fn locate(geocoder: &CsvGeocoder, address: &str, city: &str) -> Option<(f64, f64)> {
+        geocoder
+            .locate(address, city)
+            .await
+            .unwrap()
+            .map(|c| (c.latitude, c.longitude))
+    }
+
+    #[tokio::test]
+    async fn quoted_commas_stay_in_the_address() {
+        let geocoder = csv("New York,\"123 Main St, Suite \"\"A\"\"\",40.7,-74.0\n").unwrap();
+        assert_eq!(
+            locate(&geocoder, "123 main st,  suite \"a\"", "new york").await,
+            Some((40.7, -74.0))
+        );
+    }
+
+    #[tokio::test]
+    async fn unquoted_commas_stay_in_the_address() {
+        let geocoder = csv("Chicago,789 Elm St, Floor 2,41.9,-87.6\n").unwrap();
+        assert_eq!(
+            locate(&geocoder, "789 Elm St, Floor 2", "Chicago").await,
+            Some((41.9, -87.6))
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_addresses_fall_back_to_the_city() {
+        let geocoder =
+            csv("Los Angeles,456 Park Ave,34.1,-118.2\nLos Angeles,,34.0,-118.0\n").unwrap();
+        assert_eq!(
+            locate(&geocoder, "456 Park Ave", "Los Angeles").await,
+            Some((34.1, -118.2))
+        );
+        assert_eq!(
+            locate(&geocoder, "1 Other Rd", "los angeles").await,
+            Some((34.0, -118.0))
+        );
+        assert_eq!(locate(&geocoder, "456 Park Ave", "Boston").await, None);
+    }
+
+    #[test]
+    fn blank_lines_and_the_header_are_skipped() {
+        assert!(csv("\nNew York,,40.7,-74.0\n\n").is_ok());
+        assert!(CsvGeocoder::parse("not,a,valid,row").is_ok());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(csv("New York,40.7,-74.0\n").is_err());
+        assert!(csv("New York,123 Main St,north,-74.0\n").is_err());
+        assert!(csv("New York,\"123 Main St,40.7,-74.0\n").is_err());
+    }
+
+    #[test]
+    fn out_of_range_coordinates_are_rejected() {
+        assert!(csv("New York,,90.5,-74.0\n").is_err());
+        assert!(csv("New York,,40.7,180.5\n").is_err());
+        assert!(Coordinates::new(-90.0, 180.0).is_ok());
+    }
+
+    #[test]
+    fn origin_needs_lat_and_lon_together() {
+        assert!(matches!(origin(None, None, None), Ok(None)));
+        assert!(origin(Some(40.7), None, None).is_err());
+        assert!(origin(None, Some(-74.0), None).is_err());
+        assert!(origin(None, None, Some(5.0)).is_err());
+        let Ok(Some(found)) = origin(Some(40.7), Some(-74.0), Some(5.0)) else {
+            panic!("a full origin wasn't accepted");
+        };
+        assert_eq!(found.radius_km, Some(5.0));
+    }
+
+    #[test]
+    fn origin_needs_a_positive_radius() {
+        assert!(origin(Some(40.7), Some(-74.0), Some(0.0)).is_err());
+        assert!(origin(Some(40.7), Some(-74.0), Some(-1.0)).is_err());
+        assert!(origin(Some(40.7), Some(-74.0), Some(f64::NAN)).is_err());
+        assert!(origin(Some(95.0), Some(-74.0), None).is_err());
+    }
+}
@@ -1,80 +1,166 @@
+use auth::{AuthUser, DoctorOrAdmin, RequireAdmin, RequireDoctor, RequirePatient, Role};
 use axum::{
-    extract::Query,
-    http::{
-        header::{HeaderMap, AUTHORIZATION},
-        Method, StatusCode,
+    extract::State,
+    http::{header::HeaderName, HeaderValue, Method, Request},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
     },
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Json, Router,
 };
+use chrono::Utc;
+use database::Database;
 use db_structs::*;
+use error::AppError;
+use extract::{AppJson, AppPath, AppQuery};
+use std::convert::Infallible;
 use std::net::SocketAddr;
-use tokio;
+use std::sync::Arc;
+use tokio_stream::Stream;
 use tower_http::cors::{Any, CorsLayer};
-use tracing;
-use tracing_subscriber;
 
+mod archiver;
+mod auth;
 mod database;
 mod db_structs;
+mod delivery;
+mod error;
+mod events;
+mod extract;
+mod geocoding;
+mod migrate;
+mod pagination;
+mod reminders;
+mod slots;
+mod status;
+mod webhooks;
+mod zones;
 
-async fn authenticate(
-    conn: &database::Database,
-    headers: HeaderMap,
-    given_id: &i64,
-    isdoctor: bool,
-) -> bool {
-    let Some(entry) = headers.get(AUTHORIZATION) else {
-        tracing::error!("No JWT given in request, denying access..");
-        return false;
-    };
-    let Ok(rawjwt) = entry.to_str() else {
-        tracing::error!("JWT can't be parsed, denying access..");
-        return false;
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    //the pool is built once here and shared by every handler through the router state
+    let Some(mut conn) = database::init().await else {
+        tracing::error!("Could not initialise database, exiting");
+        std::process::exit(1);
     };
-    match conn.verify_jwt(rawjwt) {
-        Some(jwt) => {
-            tracing::debug!("Verified and parsed JWT");
-            if *given_id == jwt.id && isdoctor == jwt.isdoctor {
-                tracing::debug!("Correct JWT is given!");
-                return true;
-            } else {
-                tracing::error!("Incorrect JWT!");
-                return false;
+    let args: Vec<String> = std::env::args().collect();
+    //`excalibur23 migrate up|down|status` manages the schema without starting the server
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        let res = match args.get(2).map(String::as_str) {
+            Some("up") => migrate::up(conn.pool()).await,
+            Some("down") => migrate::down(conn.pool()).await,
+            Some("status") => migrate::status(conn.pool()).await,
+            _ => {
+                eprintln!("usage: {} migrate up|down|status", args[0]);
+                std::process::exit(2);
             }
+        };
+        if let Err(e) = res {
+            tracing::error!("Migration failed: {}", e);
+            std::process::exit(1);
         }
-        None => {
-            tracing::debug!("Could not verify JWT!");
-            false
+        return;
+    }
+    match geocoding::from_env() {
+        Ok(geocoder) => conn.set_geocoder(geocoder),
+        Err(e) => {
+            tracing::error!("Could not set up geocoder: {}", e);
+            std::process::exit(1);
         }
     }
-}
-
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+    //`excalibur23 geocode` looks up the coordinates of doctors that have none
+    if args.get(1).map(String::as_str) == Some("geocode") {
+        match conn.geocode_doctors().await {
+            Ok(found) => tracing::info!("Found coordinates for {} doctors", found),
+            Err(_) => {
+                tracing::error!("Geocoding doctors failed");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if database::env_or("AUTO_MIGRATE", true) {
+        if let Err(e) = migrate::up(conn.pool()).await {
+            tracing::error!("Could not apply migrations: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let transports = match delivery::Transports::from_env() {
+        Ok(transports) => transports,
+        Err(e) => {
+            tracing::error!("Could not set up message delivery: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let conn = Arc::new(conn);
+    archiver::spawn(conn.clone());
+    reminders::spawn(conn.clone());
+    delivery::spawn(conn.clone(), transports);
+    events::spawn(conn.clone());
+    webhooks::spawn(conn.clone());
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_headers(Any)
         .expose_headers(Any)
-        .allow_methods([Method::GET, Method::POST]);
-    let app = Router::new()
-        .route("/", get(root))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE]);
+    //resources addressed by path; they replace the verb-like legacy routes below
+    let v1 = Router::new()
+        .route("/patients/:id", get(v1patient))
+        .route("/patients/:id/appointments", get(v1patientappointments))
+        .route("/doctors/:id/appointments", get(v1doctorappointments))
+        .route("/appointments/:id", delete(v1cancelappointment));
+    let legacy = Router::new()
         .route("/prevapp", post(prevapp))
         .route("/doctorappointments", post(doctorappointments))
-        .route("/doctors", post(doctors))
         .route("/patient", post(patient))
+        .route("/cancelappointment", post(cancelappointment))
+        .route_layer(middleware::from_fn(deprecated));
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/doctors", post(doctors))
+        .route("/doctors/:id", get(doctorprofile))
+        .route("/doctors/:id/schedule", get(doctorschedule))
+        .route("/doctors/:id/slots", get(slots))
+        .route("/doctors/:id/reviews", get(doctorreviews))
+        .route("/reviews", post(newreview).get(moderationreviews))
+        .route("/reviews/:id/reply", post(replyreview))
+        .route("/reviews/:id/moderation", post(moderatereview))
+        .route("/schedule", post(setschedule))
+        .route("/profile", post(updateprofile))
+        .route("/prices", get(prices).post(setprice))
+        .route("/prices/history", get(pricehistory))
+        .route("/prices/:apptype", delete(withdrawprice))
         .route("/find", get(find))
+        .route("/search", get(search))
         .route("/login", post(login))
+        .route("/token/refresh", post(refresh))
         .route("/newpatient", post(newpatient))
         .route("/newdoctor", post(newdoctor))
         .route("/newappointment", post(newappointment))
-        .route("/cancelappointment", post(cancelappointment))
+        .route("/rescheduleappointment", post(rescheduleappointment))
+        .route("/appointmentstatus", post(appointmentstatus))
+        .route("/events", get(appointmentevents))
+        .route("/notifications", get(notifications))
+        .route("/notifications/:id/read", post(readnotification))
+        .route("/webhooks", post(newwebhook).get(listwebhooks))
+        .route("/webhooks/:id", delete(deletewebhook))
+        .route("/webhooks/:id/deliveries", get(webhookdeliveries))
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/replay",
+            post(replaywebhookdelivery),
+        )
         .route("/specialities", get(specialities))
         .route("/cities", get(cities))
         .route("/apptypes", get(apptypes))
         .route("/prescriptions", post(prescriptions))
-        .layer(cors);
+        .nest("/v1", v1)
+        .merge(legacy)
+        .fallback(notfound)
+        .layer(cors)
+        .with_state(conn.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     tracing::debug!("listening on {}", addr);
@@ -88,369 +174,595 @@ async fn root() -> &'static str {
     "Hello world"
 }
 
-async fn prescriptions(headers: HeaderMap, Json(payload): Json<PatientID>) -> Response {
+//unknown routes get the same JSON error body as everything else
+async fn notfound() -> AppError {
+    AppError::NotFound(String::from("No such endpoint"))
+}
+
+//legacy routes still work but tell clients to move to their /v1 replacement
+async fn deprecated<B>(request: Request<B>, next: Next<B>) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    response
+}
+
+async fn v1patient(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppPath(patient_id): AppPath<i64>,
+) -> Result<Json<PatientInfo>, AppError> {
     tracing::debug!(
-        "Got request to view previous appointments for patient ID {}",
-        payload.patient_id
+        "Got request to view patient info corresponding to patient ID {}",
+        patient_id
     );
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, false).await {
-                let res = conn.view_prescriptions(payload.patient_id).await;
-                res
-            } else {
-                code = StatusCode::UNAUTHORIZED;
-                let res: Vec<Prescriptions> = Vec::new();
-                res
-            }
-        }
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<Prescriptions> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    user.ensure_is(Role::Patient, patient_id)?;
+    Ok(Json(conn.view_patient_info(patient_id).await?))
+}
+
+async fn v1patientappointments(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppPath(patient_id): AppPath<i64>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<PrevAppointments>>, AppError> {
+    tracing::debug!(
+        "Got request to view appointments for patient ID {}",
+        patient_id
+    );
+    user.ensure_is(Role::Patient, patient_id)?;
+    Ok(Json(conn.view_prev_appointments(patient_id, &page).await?))
 }
 
-async fn doctorappointments(headers: HeaderMap, Json(payload): Json<PatientID>) -> Response {
+async fn v1doctorappointments(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppPath(doctor_id): AppPath<i64>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<DoctorAppointments>>, AppError> {
     tracing::debug!(
         "Got request to view appointments for doctor ID {}",
-        payload.patient_id
+        doctor_id
     );
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, true).await {
-                let res = conn.view_doctor_appointments(payload.patient_id).await;
-                res
-            } else {
-                code = StatusCode::UNAUTHORIZED;
-                let res: Vec<DoctorAppointments> = Vec::new();
-                res
-            }
-        }
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<DoctorAppointments> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    user.ensure_is(Role::Doctor, doctor_id)?;
+    Ok(Json(conn.view_doctor_appointments(doctor_id, &page).await?))
 }
 
-async fn prevapp(headers: HeaderMap, Json(payload): Json<PatientID>) -> Response {
+async fn v1cancelappointment(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppPath(appointment_id): AppPath<i64>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to cancel appointment ID {}", appointment_id);
+    conn.cancel_appointment_by_id(appointment_id, user.id)
+        .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Cancelled"))
+}
+
+async fn prescriptions(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<Prescriptions>>, AppError> {
+    tracing::debug!(
+        "Got request to view prescriptions for patient ID {}",
+        user.id
+    );
+    Ok(Json(conn.view_prescriptions(user.id, &page).await?))
+}
+
+async fn doctorappointments(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<DoctorAppointments>>, AppError> {
+    tracing::debug!("Got request to view appointments for doctor ID {}", user.id);
+    Ok(Json(conn.view_doctor_appointments(user.id, &page).await?))
+}
+
+async fn prevapp(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<PrevAppointments>>, AppError> {
     tracing::debug!(
         "Got request to view previous appointments for patient ID {}",
-        payload.patient_id
+        user.id
     );
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, false).await {
-                let res = conn.view_prev_appointments(payload.patient_id).await;
-                res
-            } else {
-                code = StatusCode::UNAUTHORIZED;
-                let res: Vec<PrevAppointments> = Vec::new();
-                res
-            }
-        }
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<PrevAppointments> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    Ok(Json(conn.view_prev_appointments(user.id, &page).await?))
 }
 
-async fn doctors(Json(payload): Json<City>) -> Response {
+async fn doctors(
+    State(conn): State<Arc<Database>>,
+    AppQuery(page): AppQuery<CursorQuery>,
+    AppJson(payload): AppJson<City>,
+) -> Result<Json<Page<DoctorInfo>>, AppError> {
     tracing::debug!("Got request to view doctors in city {}", payload.city);
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => conn.view_same_city_doctors(payload.city).await,
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<DoctorInfo> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    let origin = geocoding::origin(payload.lat, payload.lon, payload.radius_km)?;
+    Ok(Json(
+        conn.view_same_city_doctors(&payload.city, origin, &page)
+            .await?,
+    ))
+}
+
+async fn doctorprofile(
+    State(conn): State<Arc<Database>>,
+    AppPath(doctor_id): AppPath<i64>,
+) -> Result<Json<DoctorProfile>, AppError> {
+    tracing::debug!("Got request to view profile of doctor ID {}", doctor_id);
+    Ok(Json(conn.view_doctor_profile(doctor_id).await?))
 }
 
-async fn patient(headers: HeaderMap, Json(payload): Json<PatientID>) -> Response {
+async fn updateprofile(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppJson(payload): AppJson<ProfileUpdate>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to update profile of doctor ID {}", user.id);
+    conn.update_doctor_profile(user.id, &payload).await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+async fn prices(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+) -> Result<Json<Vec<AppointmentPrice>>, AppError> {
+    tracing::debug!("Got request to view prices of doctor ID {}", user.id);
+    Ok(Json(conn.view_offered_appointments(user.id).await?))
+}
+
+async fn setprice(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppJson(payload): AppJson<PriceUpdate>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to set price of appointment type {} to {}",
+        payload.apptype,
+        payload.price
+    );
+    conn.set_price(
+        user.id,
+        payload.apptype,
+        payload.price,
+        payload.modes.as_deref(),
+    )
+    .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+async fn withdrawprice(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppPath(apptype): AppPath<i64>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to withdraw appointment type {}", apptype);
+    conn.withdraw_price(user.id, apptype).await?;
+    tracing::debug!("Record deleted successfully");
+    Ok(Json("Deleted"))
+}
+
+async fn pricehistory(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<PriceChange>>, AppError> {
+    tracing::debug!("Got request to view price history of doctor ID {}", user.id);
+    Ok(Json(conn.view_price_history(user.id, &page).await?))
+}
+
+async fn doctorschedule(
+    State(conn): State<Arc<Database>>,
+    AppPath(doctor_id): AppPath<i64>,
+) -> Result<Json<Vec<WorkingHours>>, AppError> {
+    tracing::debug!(
+        "Got request to view working hours of doctor ID {}",
+        doctor_id
+    );
+    Ok(Json(conn.view_schedule(doctor_id).await?))
+}
+
+async fn setschedule(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppJson(payload): AppJson<Schedule>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to set working hours of doctor ID {}", user.id);
+    conn.set_schedule(user.id, &payload.hours).await?;
+    Ok(Json("Updated"))
+}
+
+async fn slots(
+    State(conn): State<Arc<Database>>,
+    AppPath(doctor_id): AppPath<i64>,
+    AppQuery(payload): AppQuery<SlotQuery>,
+) -> Result<Json<Vec<Slot>>, AppError> {
+    tracing::debug!(
+        "Got request to view free slots of doctor ID {} from {} to {}",
+        doctor_id,
+        payload.from,
+        payload.to
+    );
+    Ok(Json(
+        conn.view_free_slots(
+            doctor_id,
+            payload.apptype,
+            payload.from,
+            payload.to,
+            payload.tz.as_deref(),
+        )
+        .await?,
+    ))
+}
+
+async fn patient(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+) -> Result<Json<PatientInfo>, AppError> {
     tracing::debug!(
         "Got request to view patient info corresponding to patient ID {}",
-        payload.patient_id
+        user.id
     );
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, false).await {
-                conn.view_patient_info(payload.patient_id).await
-            } else {
-                code = StatusCode::UNAUTHORIZED;
-                let res: Vec<PatientInfo> = Vec::new();
-                res
-            }
-        }
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<PatientInfo> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    Ok(Json(conn.view_patient_info(user.id).await?))
 }
 
-async fn find(payload: Query<CityApptype>) -> Response {
+async fn find(
+    State(conn): State<Arc<Database>>,
+    AppQuery(payload): AppQuery<FindQuery>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<FoundDoctor>>, AppError> {
     tracing::debug!(
         "Got request to view all doctors with appointment type {} in city {}",
         payload.apptype,
         payload.city
     );
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => {
-            conn.view_doctor_prices(&payload.city, &payload.apptype)
-                .await
-        }
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<DoctorPrices> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    (code, Json(res)).into_response()
+    let origin = geocoding::origin(payload.lat, payload.lon, payload.radius_km)?;
+    Ok(Json(
+        conn.view_doctor_prices(&payload, origin, &page).await?,
+    ))
 }
 
-async fn newpatient(Json(payload): Json<Patient>) -> Response {
+async fn search(
+    State(conn): State<Arc<Database>>,
+    AppQuery(payload): AppQuery<SearchQuery>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<DoctorSearchResult>>, AppError> {
+    tracing::debug!("Got request to search doctors for {:?}", payload.q);
+    Ok(Json(conn.search_doctors(&payload, &page).await?))
+}
+
+async fn newpatient(
+    State(conn): State<Arc<Database>>,
+    AppJson(payload): AppJson<Patient>,
+) -> Result<Json<&'static str>, AppError> {
     tracing::debug!("Got request to insert new patient info");
-    match database::init().await {
-        Some(conn) => {
-            let res = conn
-                .add_new_patient(&payload.name, &payload.email, &payload.phone)
-                .await
-                && conn
-                    .register(&payload.email, &payload.password, false)
-                    .await;
-            if res {
-                tracing::debug!("Record inserted successfully");
-                return (StatusCode::OK, Json("Inserted")).into_response();
-            } else {
-                return (StatusCode::BAD_REQUEST, Json("Error while inserting")).into_response();
-            }
-        }
-        None => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Error while inserting"),
-            )
-                .into_response();
-        }
-    }
+    let time_zone = zones::parse(payload.time_zone.as_deref().unwrap_or(zones::DEFAULT_ZONE))?;
+    conn.add_patient_with_login(&payload, time_zone).await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json("Inserted"))
 }
 
-async fn newdoctor(Json(payload): Json<Doctor>) -> Response {
+async fn newdoctor(
+    State(conn): State<Arc<Database>>,
+    AppJson(payload): AppJson<Doctor>,
+) -> Result<Json<&'static str>, AppError> {
     tracing::debug!("Got request to insert new doctor info");
-    match database::init().await {
-        Some(conn) => {
-            let mut res = conn.register(&payload.email, &payload.password, true).await;
-            if !res {
-                tracing::error!("Record could not be inserted successfully");
-                return (StatusCode::BAD_REQUEST, Json("Error while inserting")).into_response();
-            }
-            res = res && conn
-                .add_new_doctor(
-                    &payload.name,
-                    payload.speciality,
-                    &payload.city,
-                    &payload.address,
-                    &payload.email,
-                    &payload.phone,
-                )
-                .await;
-            if res {
-                tracing::debug!("Record inserted successfully");
-                return (StatusCode::OK, Json("Inserted")).into_response();
-            } else {
-                tracing::error!("Record could not be inserted successfully");
-                return (StatusCode::BAD_REQUEST, Json("Error while inserting")).into_response();
-            }
-        }
-        None => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Error while inserting"),
-            )
-                .into_response();
-        }
-    }
+    let time_zone = zones::parse(payload.time_zone.as_deref().unwrap_or(zones::DEFAULT_ZONE))?;
+    let location = conn
+        .doctor_location(
+            payload.latitude,
+            payload.longitude,
+            &payload.address,
+            &payload.city,
+        )
+        .await?;
+    conn.add_doctor_with_login(&payload, time_zone, location)
+        .await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json("Inserted"))
 }
 
-async fn newappointment(headers: HeaderMap, Json(payload): Json<Appointment>) -> Response {
+async fn newappointment(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppJson(payload): AppJson<Appointment>,
+) -> Result<Json<&'static str>, AppError> {
     tracing::debug!("Got request to insert new appointment info");
-    match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, false).await {
-                let res = conn
-                    .add_new_appointment(
-                        payload.doctor_id,
-                        payload.patient_id,
-                        payload.apptype,
-                        &payload.datetime,
-                        &payload.phyorvirt,
-                        &payload.status,
-                        &payload.prescription,
-                    )
-                    .await;
-                if res {
-                    tracing::debug!("Record inserted successfully");
-                    return (StatusCode::OK, Json("Inserted")).into_response();
-                } else {
-                    return (StatusCode::BAD_REQUEST, Json("Error while inserting"))
-                        .into_response();
-                }
-            } else {
-                return (StatusCode::BAD_REQUEST, Json("Error while inserting")).into_response();
-            }
-        }
-        None => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Error while inserting"),
-            )
-                .into_response();
-        }
-    }
+    conn.add_new_appointment(
+        payload.doctor_id,
+        user.id,
+        payload.apptype,
+        payload.datetime.with_timezone(&Utc),
+        &payload.phyorvirt,
+    )
+    .await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json("Inserted"))
 }
 
-async fn cancelappointment(headers: HeaderMap, Json(payload): Json<CancelAppointment>) -> Response {
+async fn cancelappointment(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppJson(payload): AppJson<CancelAppointment>,
+) -> Result<Json<&'static str>, AppError> {
     tracing::debug!("Got request to cancel appointment");
-    match database::init().await {
-        Some(conn) => {
-            if authenticate(&conn, headers, &payload.patient_id, false).await {
-                let res = conn
-                    .cancel_appointment(
-                        payload.doctor_id,
-                        payload.patient_id,
-                        &payload.datetime,
-                    )
-                    .await;
-                if res {
-                    tracing::debug!("Record updated successfully");
-                    return (StatusCode::OK, Json("Cancelled")).into_response();
-                } else {
-                    return (StatusCode::BAD_REQUEST, Json("Error while cancelling"))
-                        .into_response();
-                }
-            } else {
-                return (StatusCode::BAD_REQUEST, Json("Error while cancelling")).into_response();
-            }
-        }
-        None => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Error while cancelling"),
-            )
-                .into_response();
-        }
-    }
+    conn.cancel_appointment(
+        payload.doctor_id,
+        user.id,
+        payload.datetime.with_timezone(&Utc),
+    )
+    .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Cancelled"))
 }
 
-async fn cities() -> Response {
-    tracing::debug!("Got request to fetch cities");
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => conn.view_cities().await,
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<Cities> = Vec::new();
-            res
-        }
+async fn rescheduleappointment(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppJson(payload): AppJson<RescheduleAppointment>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to reschedule appointment ID {}",
+        payload.appointment_id
+    );
+    conn.reschedule_appointment(
+        payload.appointment_id,
+        user.id,
+        user.role == Role::Doctor,
+        payload.new_datetime.with_timezone(&Utc),
+    )
+    .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Rescheduled"))
+}
+
+async fn appointmentstatus(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppJson(payload): AppJson<AppointmentStatusUpdate>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to set status of appointment ID {} to {}",
+        payload.appointment_id,
+        payload.status
+    );
+    conn.update_appointment_status(
+        payload.appointment_id,
+        user.id,
+        payload.status.parse()?,
+        payload.prescription.as_deref(),
+    )
+    .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+//browsers can't set headers on an EventSource, so the token may also be given in the URL
+async fn appointmentevents(
+    State(conn): State<Arc<Database>>,
+    user: Option<AuthUser>,
+    AppQuery(payload): AppQuery<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let user = match (user, &payload.access_token) {
+        (Some(user), _) => user,
+        (None, Some(token)) => AuthUser::from(conn.verify_jwt(token)?),
+        (None, None) => return Err(AppError::Unauthorized(String::from("No token given"))),
     };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    return (code, Json(res)).into_response();
+    tracing::debug!(
+        "Got request to stream events of {:?} ID {}",
+        user.role,
+        user.id
+    );
+    Ok(Sse::new(events::stream(&conn, user)).keep_alive(KeepAlive::default()))
+}
+
+async fn notifications(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppQuery(payload): AppQuery<NotificationQuery>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<Notification>>, AppError> {
+    tracing::debug!(
+        "Got request to view notifications of {:?} ID {}",
+        user.role,
+        user.id
+    );
+    Ok(Json(
+        conn.view_notifications(user.id, user.role == Role::Doctor, payload.unread, &page)
+            .await?,
+    ))
+}
+
+async fn readnotification(
+    State(conn): State<Arc<Database>>,
+    user: AuthUser,
+    AppPath(notification_id): AppPath<i64>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to mark notification ID {} as read",
+        notification_id
+    );
+    conn.mark_notification_read(notification_id, user.id, user.role == Role::Doctor)
+        .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+async fn newreview(
+    State(conn): State<Arc<Database>>,
+    RequirePatient(user): RequirePatient,
+    AppJson(payload): AppJson<NewReview>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to review appointment ID {}",
+        payload.appointment_id
+    );
+    conn.add_review(
+        user.id,
+        payload.appointment_id,
+        payload.rating,
+        payload.comment.as_deref(),
+    )
+    .await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json("Inserted"))
 }
 
-async fn apptypes() -> Response {
+async fn doctorreviews(
+    State(conn): State<Arc<Database>>,
+    AppPath(doctor_id): AppPath<i64>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<Review>>, AppError> {
+    tracing::debug!("Got request to view reviews of doctor ID {}", doctor_id);
+    Ok(Json(conn.view_doctor_reviews(doctor_id, &page).await?))
+}
+
+async fn replyreview(
+    State(conn): State<Arc<Database>>,
+    RequireDoctor(user): RequireDoctor,
+    AppPath(review_id): AppPath<i64>,
+    AppJson(payload): AppJson<ReviewReply>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to reply to review ID {}", review_id);
+    conn.reply_to_review(review_id, user.id, &payload.reply)
+        .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+async fn moderatereview(
+    State(conn): State<Arc<Database>>,
+    _: RequireAdmin,
+    AppPath(review_id): AppPath<i64>,
+    AppJson(payload): AppJson<ReviewModeration>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to set review ID {} hidden: {}",
+        review_id,
+        payload.hidden
+    );
+    conn.moderate_review(review_id, payload.hidden, payload.reason.as_deref())
+        .await?;
+    tracing::debug!("Record updated successfully");
+    Ok(Json("Updated"))
+}
+
+async fn moderationreviews(
+    State(conn): State<Arc<Database>>,
+    _: RequireAdmin,
+    AppQuery(payload): AppQuery<ModerationQuery>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<ModeratedReview>>, AppError> {
+    tracing::debug!("Got request to view reviews for moderation");
+    Ok(Json(
+        conn.view_reviews_for_moderation(payload.hidden, &page)
+            .await?,
+    ))
+}
+
+async fn newwebhook(
+    State(conn): State<Arc<Database>>,
+    DoctorOrAdmin(owner): DoctorOrAdmin,
+    AppJson(payload): AppJson<NewWebhook>,
+) -> Result<Json<CreatedWebhook>, AppError> {
+    tracing::debug!("Got request to add webhook for {}", payload.url);
+    let webhook = conn
+        .create_webhook(owner, &payload.url, &payload.events)
+        .await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json(webhook))
+}
+
+async fn listwebhooks(
+    State(conn): State<Arc<Database>>,
+    DoctorOrAdmin(owner): DoctorOrAdmin,
+) -> Result<Json<Vec<Webhook>>, AppError> {
+    tracing::debug!("Got request to list webhooks");
+    Ok(Json(conn.view_webhooks(owner).await?))
+}
+
+async fn deletewebhook(
+    State(conn): State<Arc<Database>>,
+    DoctorOrAdmin(owner): DoctorOrAdmin,
+    AppPath(webhook_id): AppPath<i64>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!("Got request to delete webhook ID {}", webhook_id);
+    conn.delete_webhook(webhook_id, owner).await?;
+    tracing::debug!("Record deleted successfully");
+    Ok(Json("Deleted"))
+}
+
+async fn webhookdeliveries(
+    State(conn): State<Arc<Database>>,
+    DoctorOrAdmin(owner): DoctorOrAdmin,
+    AppPath(webhook_id): AppPath<i64>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<WebhookDelivery>>, AppError> {
+    tracing::debug!(
+        "Got request to view deliveries of webhook ID {}",
+        webhook_id
+    );
+    Ok(Json(
+        conn.view_webhook_deliveries(webhook_id, owner, &page)
+            .await?,
+    ))
+}
+
+async fn replaywebhookdelivery(
+    State(conn): State<Arc<Database>>,
+    DoctorOrAdmin(owner): DoctorOrAdmin,
+    AppPath((webhook_id, delivery_id)): AppPath<(i64, i64)>,
+) -> Result<Json<&'static str>, AppError> {
+    tracing::debug!(
+        "Got request to replay delivery ID {} of webhook ID {}",
+        delivery_id,
+        webhook_id
+    );
+    conn.replay_webhook_delivery(webhook_id, delivery_id, owner)
+        .await?;
+    tracing::debug!("Record inserted successfully");
+    Ok(Json("Queued"))
+}
+
+async fn cities(
+    State(conn): State<Arc<Database>>,
+    AppQuery(page): AppQuery<CursorQuery>,
+) -> Result<Json<Page<Cities>>, AppError> {
+    tracing::debug!("Got request to fetch cities");
+    Ok(Json(conn.view_cities(&page).await?))
+}
+
+async fn apptypes(State(conn): State<Arc<Database>>) -> Result<Json<Vec<Apptypes>>, AppError> {
     tracing::debug!("Got request to fetch appointment types");
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => conn.view_appointment_types().await,
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<Apptypes> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    return (code, Json(res)).into_response();
+    Ok(Json(conn.view_appointment_types().await?))
 }
 
-async fn specialities() -> Response {
+async fn specialities(
+    State(conn): State<Arc<Database>>,
+) -> Result<Json<Vec<Specialities>>, AppError> {
     tracing::debug!("Got request to fetch specialities");
-    let mut code = StatusCode::OK;
-    let res = match database::init().await {
-        Some(conn) => conn.view_specialities().await,
-        None => {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            let res: Vec<Specialities> = Vec::new();
-            res
-        }
-    };
-    if res.is_empty() && code == StatusCode::OK {
-        code = StatusCode::BAD_REQUEST;
-    }
-    return (code, Json(res)).into_response();
+    Ok(Json(conn.view_specialities().await?))
 }
 
-async fn login(Json(payload): Json<Login>) -> Response {
+async fn login(
+    State(conn): State<Arc<Database>>,
+    AppJson(payload): AppJson<Login>,
+) -> Result<Json<TokenPair>, AppError> {
     tracing::debug!("Got request to login");
-    match database::init().await {
-        Some(conn) => {
-            let res = conn.login(&payload.email, &payload.password).await;
-            match res {
-                Some(jwt) => {
-                    tracing::debug!("Generated JWT successfully! {}", jwt);
-                    return (StatusCode::OK, Json(jwt)).into_response();
-                }
-                None => {
-                    return (StatusCode::BAD_REQUEST, Json("Error while logging in"))
-                        .into_response();
-                }
-            }
-        }
-        None => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Error while logging in"),
-            )
-                .into_response();
-        }
-    }
+    let tokens = conn.login(&payload.email, &payload.password).await?;
+    tracing::debug!("Generated JWT successfully!");
+    Ok(Json(tokens))
+}
+
+async fn refresh(
+    State(conn): State<Arc<Database>>,
+    AppJson(payload): AppJson<RefreshToken>,
+) -> Result<Json<TokenPair>, AppError> {
+    tracing::debug!("Got request to refresh tokens");
+    Ok(Json(conn.refresh_tokens(&payload.refresh_token).await?))
 }
@@ -0,0 +1,60 @@
+//versioned schema migrations, embedded from the migrations/ directory at compile time
+//applied versions are tracked by sqlx in the _sqlx_migrations table
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    Pool, Postgres,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+//applies every pending migration
+pub async fn up(pool: &Pool<Postgres>) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await?;
+    tracing::info!("Database schema is up to date");
+    Ok(())
+}
+
+//reverts only the most recently applied migration
+pub async fn down(pool: &Pool<Postgres>) -> Result<(), MigrateError> {
+    let mut applied = applied_versions(pool).await?;
+    applied.sort_unstable();
+    let Some(latest) = applied.pop() else {
+        tracing::info!("No migrations applied, nothing to revert");
+        return Ok(());
+    };
+    let target = applied.pop().unwrap_or(0);
+    MIGRATOR.undo(pool, target).await?;
+    tracing::info!("Reverted migration {}", latest);
+    Ok(())
+}
+
+//prints every known migration and whether it has been applied
+pub async fn status(pool: &Pool<Postgres>) -> Result<(), MigrateError> {
+    let applied = applied_versions(pool).await?;
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        let state = if applied.contains(&migration.version) {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!(
+            "{:>4} {:<8} {}",
+            migration.version, state, migration.description
+        );
+    }
+    Ok(())
+}
+
+async fn applied_versions(pool: &Pool<Postgres>) -> Result<Vec<i64>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect())
+}
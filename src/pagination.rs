@@ -0,0 +1,98 @@
+//keyset pagination for long lists: a page's cursor is the sort key of its last row, and the next
+//page starts right after that key, so rows added meanwhile don't shift or repeat later pages
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::db_structs::{CursorQuery, Page};
+use crate::error::AppError;
+
+//largest page of a paginated list
+const MAX_PAGE_SIZE: i64 = 100;
+
+//page size when the request doesn't give a limit
+const DEFAULT_LIMIT: i64 = 50;
+
+//rows of a list paginated by cursor; the key holds the values the list is ordered by, ending in
+//something unique so no two rows have the same key
+pub trait Keyset {
+    type Key: Serialize + DeserializeOwned;
+
+    fn key(&self) -> Self::Key;
+}
+
+pub struct Cursor<R: Keyset> {
+    pub limit: i64,
+    //key of the last row of the previous page, None for the first page
+    pub after: Option<R::Key>,
+}
+
+impl<R: Keyset> Cursor<R> {
+    pub fn parse(query: &CursorQuery) -> Result<Self, AppError> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(AppError::Validation(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        let after = match query.cursor.as_deref() {
+            None | Some("") => None,
+            Some(cursor) => Some(decode(cursor).ok_or_else(|| {
+                AppError::Validation(String::from("cursor is not valid for this list"))
+            })?),
+        };
+        Ok(Cursor { limit, after })
+    }
+
+    //one row more than fits on the page, it only shows whether there is a next page
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    //takes rows fetched after the cursor in order, at most fetch_limit of them
+    pub fn page(&self, mut rows: Vec<R>) -> Page<R> {
+        let more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+        let next_cursor = if more {
+            rows.last().map(|row| encode(&row.key()))
+        } else {
+            None
+        };
+        Page {
+            items: rows,
+            next_cursor,
+        }
+    }
+
+    //like page, for rows filtered after fetching them; scanned_to is the key of the last row
+    //looked at when there are more to look at, so the next page starts after it even if too few
+    //rows were kept to fill this one
+    pub fn filtered_page(&self, rows: Vec<R>, scanned_to: Option<R::Key>) -> Page<R> {
+        if rows.len() as i64 > self.limit {
+            return self.page(rows);
+        }
+        Page {
+            items: rows,
+            next_cursor: scanned_to.map(|key| encode(&key)),
+        }
+    }
+}
+
+//cursors are hex encoded JSON, clients should treat them as opaque
+fn encode<K: Serialize>(key: &K) -> String {
+    serde_json::to_vec(key)
+        .unwrap_or_default()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode<K: DeserializeOwned>(cursor: &str) -> Option<K> {
+    if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+        return None;
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    serde_json::from_slice(&bytes).ok()
+}
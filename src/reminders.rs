@@ -0,0 +1,47 @@
+//background task that reminds patients of their upcoming appointments
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::database::{env_or, Database};
+
+pub fn spawn(conn: Arc<Database>) {
+    let offsets = offsets(&env_or("REMINDER_OFFSETS_MINUTES", String::from("1440,60")));
+    let every_secs: u64 = env_or("REMINDER_INTERVAL_SECS", 60);
+    tracing::debug!(
+        "Sending reminders {:?} minutes before appointments, checking every {}s",
+        offsets,
+        every_secs
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(every_secs.max(1)));
+        loop {
+            interval.tick().await;
+            //offsets are largest first, each one only covers appointments the next one doesn't
+            for (i, &offset) in offsets.iter().enumerate() {
+                let next = offsets.get(i + 1).copied().unwrap_or(0);
+                match conn.send_due_reminders(offset, next).await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("Sent {} reminders {} minutes ahead", n, offset),
+                    Err(_) => tracing::error!("Sending reminders failed, retrying next time"),
+                }
+            }
+        }
+    });
+}
+
+//comma separated minutes, like 1440,60 for a day and an hour before
+fn offsets(setting: &str) -> Vec<i32> {
+    let mut offsets: Vec<i32> = setting
+        .split(',')
+        .filter_map(|s| match s.trim().parse() {
+            Ok(m) if m > 0 => Some(m),
+            _ => {
+                tracing::error!("Ignoring invalid reminder offset {:?}", s);
+                None
+            }
+        })
+        .collect();
+    offsets.sort_unstable_by(|a, b| b.cmp(a));
+    offsets.dedup();
+    offsets
+}
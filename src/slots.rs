@@ -0,0 +1,236 @@
+//turns a doctor's weekly working hours into concrete bookable slots
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+use crate::db_structs::WorkingHours;
+use crate::zones;
+
+//a slot or booking as a half open [start, end) range
+pub type TimeRange = (DateTime<Utc>, DateTime<Utc>);
+
+//every slot of the given length that fits in the working hours between the two dates (inclusive),
+//starts after `not_before` and doesn't overlap any of the booked ranges
+//dates and working hours are wall clock times in the doctor's zone `tz`
+pub fn free_slots(
+    hours: &[WorkingHours],
+    tz: Tz,
+    length: Duration,
+    from: NaiveDate,
+    to: NaiveDate,
+    booked: &[TimeRange],
+    not_before: DateTime<Utc>,
+) -> Vec<TimeRange> {
+    let mut slots = Vec::new();
+    if length <= Duration::zero() {
+        return slots;
+    }
+    let mut day = from;
+    while day <= to {
+        let weekday = day.weekday().number_from_monday() as i16;
+        for interval in hours.iter().filter(|h| h.weekday == weekday) {
+            let mut start = day.and_time(interval.start_time);
+            let close = day.and_time(interval.end_time);
+            while start + length <= close {
+                //wall clock times skipped by a daylight saving change have no slot
+                if let Some(instant) = zones::to_instant(start, tz) {
+                    let end = instant + length;
+                    let taken = booked
+                        .iter()
+                        .any(|&(bstart, bend)| bstart < end && instant < bend);
+                    if instant >= not_before && !taken {
+                        slots.push((instant, end));
+                    }
+                }
+                start += length;
+            }
+        }
+        day += Duration::days(1);
+    }
+    slots.sort();
+    slots
+}
+
+//checks that no two intervals on the same weekday overlap
+pub fn hours_overlap(hours: &[WorkingHours]) -> bool {
+    hours.iter().enumerate().any(|(i, a)| {
+        hours[i + 1..].iter().any(|b| {
+            a.weekday == b.weekday && a.start_time < b.end_time && b.start_time < a.end_time
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn hours(weekday: i16, start: &str, end: &str) -> WorkingHours {
+        WorkingHours {
+            weekday,
+            start_time: NaiveTime::parse_from_str(start, "%H:%M").unwrap(),
+            end_time: NaiveTime::parse_from_str(end, "%H:%M").unwrap(),
+        }
+    }
+
+    fn at(instant: &str) -> DateTime<Utc> {
+        instant.parse().unwrap()
+    }
+
+    fn date(day: &str) -> NaiveDate {
+        day.parse().unwrap()
+    }
+
+    fn starts(slots: &[TimeRange]) -> Vec<DateTime<Utc>> {
+        slots.iter().map(|&(start, _)| start).collect()
+    }
+
+    //2026-10-19 is a Monday
+    fn monday(hours: &[WorkingHours], length: i64, booked: &[TimeRange]) -> Vec<TimeRange> {
+        let day = date("2026-10-19");
+        free_slots(
+            hours,
+            Tz::UTC,
+            Duration::minutes(length),
+            day,
+            day,
+            booked,
+            at("2026-01-01T00:00:00Z"),
+        )
+    }
+
+    #[test]
+    fn skipped_wall_clock_times_have_no_slot() {
+        //clocks in Berlin go from 02:00 to 03:00 on Sunday 2026-03-29
+        let day = date("2026-03-29");
+        let slots = free_slots(
+            &[hours(7, "01:00", "04:00")],
+            chrono_tz::Europe::Berlin,
+            Duration::minutes(30),
+            day,
+            day,
+            &[],
+            at("2026-01-01T00:00:00Z"),
+        );
+        assert_eq!(
+            starts(&slots),
+            vec![
+                at("2026-03-29T00:00:00Z"),
+                at("2026-03-29T00:30:00Z"),
+                at("2026-03-29T01:00:00Z"),
+                at("2026-03-29T01:30:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_wall_clock_times_have_one_slot() {
+        //clocks in Berlin go from 03:00 back to 02:00 on Sunday 2026-10-25
+        let day = date("2026-10-25");
+        let slots = free_slots(
+            &[hours(7, "01:00", "04:00")],
+            chrono_tz::Europe::Berlin,
+            Duration::hours(1),
+            day,
+            day,
+            &[],
+            at("2026-01-01T00:00:00Z"),
+        );
+        assert_eq!(
+            slots,
+            vec![
+                (at("2026-10-24T23:00:00Z"), at("2026-10-25T00:00:00Z")),
+                (at("2026-10-25T00:00:00Z"), at("2026-10-25T01:00:00Z")),
+                (at("2026-10-25T02:00:00Z"), at("2026-10-25T03:00:00Z")),
+            ]
+        );
+    }
+
+    #[test]
+    fn booked_slots_are_left_out() {
+        let booked = [
+            (at("2026-10-19T09:15:00Z"), at("2026-10-19T09:45:00Z")),
+            //ends right when the 10:00 slot starts, so it doesn't take it
+            (at("2026-10-19T08:00:00Z"), at("2026-10-19T10:00:00Z")),
+        ];
+        let slots = monday(&[hours(1, "09:00", "11:00")], 30, &booked);
+        assert_eq!(
+            starts(&slots),
+            vec![at("2026-10-19T10:00:00Z"), at("2026-10-19T10:30:00Z")]
+        );
+    }
+
+    #[test]
+    fn slots_before_the_cutoff_are_left_out() {
+        let day = date("2026-10-19");
+        let slots = free_slots(
+            &[hours(1, "09:00", "11:00")],
+            Tz::UTC,
+            Duration::minutes(30),
+            day,
+            day,
+            &[],
+            at("2026-10-19T09:30:00Z"),
+        );
+        assert_eq!(
+            starts(&slots),
+            vec![
+                at("2026-10-19T09:30:00Z"),
+                at("2026-10-19T10:00:00Z"),
+                at("2026-10-19T10:30:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn leftover_time_at_the_end_has_no_slot() {
+        let slots = monday(&[hours(1, "09:00", "10:00")], 25, &[]);
+        assert_eq!(
+            slots,
+            vec![
+                (at("2026-10-19T09:00:00Z"), at("2026-10-19T09:25:00Z")),
+                (at("2026-10-19T09:25:00Z"), at("2026-10-19T09:50:00Z")),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_days_in_range_with_hours_have_slots() {
+        let slots = free_slots(
+            &[hours(1, "09:00", "10:00"), hours(3, "14:00", "15:00")],
+            Tz::UTC,
+            Duration::hours(1),
+            date("2026-10-19"),
+            date("2026-10-25"),
+            &[],
+            at("2026-01-01T00:00:00Z"),
+        );
+        assert_eq!(
+            starts(&slots),
+            vec![at("2026-10-19T09:00:00Z"), at("2026-10-21T14:00:00Z")]
+        );
+        assert!(monday(&[hours(1, "09:00", "10:00")], 0, &[]).is_empty());
+    }
+
+    #[test]
+    fn overlapping_hours_on_the_same_day() {
+        assert!(hours_overlap(&[
+            hours(1, "09:00", "12:00"),
+            hours(1, "11:00", "13:00"),
+        ]));
+        assert!(hours_overlap(&[
+            hours(2, "09:00", "17:00"),
+            hours(1, "08:00", "09:00"),
+            hours(2, "10:00", "11:00"),
+        ]));
+    }
+
+    #[test]
+    fn touching_or_other_day_hours_dont_overlap() {
+        assert!(!hours_overlap(&[
+            hours(1, "09:00", "12:00"),
+            hours(1, "12:00", "15:00"),
+            hours(2, "10:00", "11:00"),
+        ]));
+        assert!(!hours_overlap(&[]));
+    }
+}
@@ -0,0 +1,72 @@
+//lifecycle of an appointment; only the transitions below are allowed
+//
+//  scheduled -> in_progress -> fulfilled
+//  scheduled -> no_show
+//  scheduled -> cancelled (only by the patient, see Database::cancel_appointment)
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AppointmentStatus {
+    Scheduled,
+    InProgress,
+    Fulfilled,
+    NoShow,
+    Cancelled,
+}
+
+impl AppointmentStatus {
+    //statuses an appointment can't move on from; only appointments in one of them are archived
+    pub const FINAL: [AppointmentStatus; 3] = [
+        AppointmentStatus::Fulfilled,
+        AppointmentStatus::NoShow,
+        AppointmentStatus::Cancelled,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppointmentStatus::Scheduled => "scheduled",
+            AppointmentStatus::InProgress => "in_progress",
+            AppointmentStatus::Fulfilled => "fulfilled",
+            AppointmentStatus::NoShow => "no_show",
+            AppointmentStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn can_become(self, next: AppointmentStatus) -> bool {
+        use AppointmentStatus::*;
+        matches!(
+            (self, next),
+            (Scheduled, InProgress)
+                | (InProgress, Fulfilled)
+                | (Scheduled, NoShow)
+                | (Scheduled, Cancelled)
+        )
+    }
+}
+
+impl fmt::Display for AppointmentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AppointmentStatus {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scheduled" => Ok(AppointmentStatus::Scheduled),
+            "in_progress" => Ok(AppointmentStatus::InProgress),
+            "fulfilled" => Ok(AppointmentStatus::Fulfilled),
+            "no_show" => Ok(AppointmentStatus::NoShow),
+            "cancelled" => Ok(AppointmentStatus::Cancelled),
+            _ => Err(AppError::Validation(format!(
+                "Unknown appointment status {}",
+                s
+            ))),
+        }
+    }
+}
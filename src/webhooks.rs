@@ -0,0 +1,228 @@
+//sends appointment events to the URLs registered by doctors and admins
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{redirect, Url};
+use sha2::Sha256;
+use sqlx::postgres::PgConnection;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::database::{env_or, Database};
+use crate::db_structs::PendingDelivery;
+use crate::delivery::{self, DeliveryError};
+use crate::error::AppError;
+use crate::events::AppointmentEvent;
+
+//events a webhook can ask for
+pub const EVENTS: [&str; 5] = [
+    "appointment.created",
+    "appointment.cancelled",
+    "appointment.rescheduled",
+    "appointment.status_changed",
+    "prescription.issued",
+];
+
+//queues the event for every webhook that wants it, in the transaction that caused the event
+pub async fn queue(
+    conn: &mut PgConnection,
+    event: &AppointmentEvent,
+    payload: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "insert into webhook_deliveries(webhook_id, event, payload)
+        select id, $1, $2 from webhooks
+        where $1 = any(events) and (doctor_id is null or doctor_id = $3)",
+    )
+    .bind(&event.kind)
+    .bind(payload)
+    .bind(event.doctor_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+//hex HMAC-SHA256 of "<timestamp>.<payload>"; receivers recompute it with their secret and
+//should reject old timestamps so that captured requests can't be replayed
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+//checks a webhook URL when it's registered, see destination
+pub async fn check_url(url: &str) -> Result<(), AppError> {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+        _ => {
+            return Err(AppError::Validation(format!(
+                "{} is not a valid http(s) URL",
+                url
+            )))
+        }
+    };
+    destination(&parsed).await.map_err(AppError::Validation)?;
+    Ok(())
+}
+
+//the address a webhook is sent to; every address the host resolves to has to be public, so
+//webhooks can't be used to reach the server's own network (WEBHOOK_ALLOW_PRIVATE=true lifts this
+//for local development)
+async fn destination(url: &Url) -> Result<SocketAddr, String> {
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| format!("{} has no port", url))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("{} has no host", url))?;
+    let addrs: Vec<SocketAddr> = match ip_literal(host) {
+        Some(ip) => vec![SocketAddr::new(ip, port)],
+        None => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+            .collect(),
+    };
+    if !env_or("WEBHOOK_ALLOW_PRIVATE", false) {
+        if let Some(addr) = addrs.iter().find(|a| !is_public(a.ip())) {
+            return Err(format!(
+                "{} points to {}, which is not a public address",
+                url,
+                addr.ip()
+            ));
+        }
+    }
+    addrs
+        .first()
+        .copied()
+        .ok_or_else(|| format!("{} doesn't resolve to any address", url))
+}
+
+//hosts given as an address rather than a name; IPv6 ones come in brackets
+fn ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+//loopback, private, link-local, unspecified and other special purpose addresses aren't public
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                //shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    //unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+pub fn spawn(conn: Arc<Database>) {
+    let every_secs: u64 = env_or("WEBHOOK_INTERVAL_SECS", 5);
+    let batch: i64 = env_or("WEBHOOK_BATCH_SIZE", 20);
+    let max_attempts: i32 = env_or("WEBHOOK_MAX_ATTEMPTS", 8);
+    tracing::debug!(
+        "Delivering up to {} webhooks every {}s, giving up after {} attempts",
+        batch,
+        every_secs,
+        max_attempts
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(every_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let deliveries = match conn.claim_webhook_deliveries(batch).await {
+                Ok(deliveries) => deliveries,
+                Err(_) => {
+                    tracing::error!("Reading webhook deliveries failed, retrying next time");
+                    continue;
+                }
+            };
+            for delivery in deliveries {
+                let attempts = delivery.attempts + 1;
+                let (code, error) = match send(&delivery).await {
+                    Ok(response) if response.status().is_success() => {
+                        (Some(response.status().as_u16()), None)
+                    }
+                    Ok(response) => (
+                        Some(response.status().as_u16()),
+                        Some(format!("Responded with {}", response.status())),
+                    ),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                let code = code.map(i32::from);
+                let saved = match error {
+                    None => conn.webhook_delivered(delivery.id, code).await,
+                    Some(error) => {
+                        tracing::error!(
+                            "Webhook delivery {} failed (attempt {}): {}",
+                            delivery.id,
+                            attempts,
+                            error
+                        );
+                        let retry_at = (attempts < max_attempts)
+                            .then(|| Utc::now() + delivery::backoff(attempts));
+                        conn.webhook_failed(delivery.id, code, &error, retry_at)
+                            .await
+                    }
+                };
+                if saved.is_err() {
+                    tracing::error!("Could not save outcome of webhook delivery {}", delivery.id);
+                }
+            }
+        }
+    });
+}
+
+//the host is checked again on every delivery, as DNS may have changed since registering, and the
+//request is pinned to the checked address; redirects aren't followed, since they could lead
+//anywhere
+async fn send(delivery: &PendingDelivery) -> Result<reqwest::Response, DeliveryError> {
+    let url = Url::parse(&delivery.url)?;
+    let addr = destination(&url).await?;
+    let mut client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(redirect::Policy::none());
+    if let Some(host) = url.host_str().filter(|h| ip_literal(h).is_none()) {
+        client = client.resolve(host, addr);
+    }
+    let timestamp = Utc::now().timestamp();
+    Ok(client
+        .build()?
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", delivery.webhook_id.to_string())
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            format!(
+                "sha256={}",
+                sign(&delivery.secret, timestamp, &delivery.payload)
+            ),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await?)
+}
@@ -0,0 +1,48 @@
+//time zone handling; times are stored as instants (timestamptz) and shown in the zone of the reader
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::error::AppError;
+
+//zone of doctors and patients that didn't pick one
+pub const DEFAULT_ZONE: &str = "UTC";
+
+//parses an IANA time zone name like Europe/Berlin
+pub fn parse(name: &str) -> Result<Tz, AppError> {
+    name.parse().map_err(|_| {
+        AppError::Validation(format!(
+            "{} is not a known time zone, use an IANA name like Europe/Berlin",
+            name
+        ))
+    })
+}
+
+//zone names are validated before they are stored, so a bad one means the database was edited by hand
+pub fn parse_stored(name: &str) -> Tz {
+    name.parse().unwrap_or_else(|_| {
+        tracing::error!("Stored time zone {} is invalid, falling back to UTC", name);
+        Tz::UTC
+    })
+}
+
+//the same instant with the offset the zone had at that moment, which serializes as RFC 3339
+pub fn localize<T: TimeZone>(instant: &DateTime<T>, tz: Tz) -> DateTime<FixedOffset> {
+    let local = instant.with_timezone(&tz);
+    local.with_timezone(&local.offset().fix())
+}
+
+//instant of a wall clock time in the zone; None if the time is skipped by a daylight saving change,
+//and the earlier of the two if it happens twice
+pub fn to_instant(local: NaiveDateTime, tz: Tz) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+//human readable time for messages, like 2023-03-01 14:30 CET
+pub fn display(instant: &DateTime<Utc>, tz: Tz) -> String {
+    instant
+        .with_timezone(&tz)
+        .format("%Y-%m-%d %H:%M %Z")
+        .to_string()
+}